@@ -0,0 +1,144 @@
+/// The longest delay a comb filter can have, which puts the lowest comb frequency at 20 Hz.
+const MAX_DELAY_MS: f32 = 50.0;
+
+/// The number of voices that can run a comb filter at the same time. Voices that start while every
+/// comb filter is in use play without one.
+const NUM_COMB_FILTERS: usize = 64;
+
+/// The feedback is capped below 1 so the comb filter can never become unstable.
+pub const MAX_FEEDBACK: f32 = 0.99;
+
+/// A stereo feedback comb filter with a fractional delay.
+#[derive(Debug, Clone)]
+pub struct CombFilter {
+    buffers: [Vec<f32>; 2],
+    write_index: usize,
+}
+
+/// A fixed set of comb filters that voices can borrow while they're playing. Voices are created for
+/// every (channel, note) pair, so giving each of them its own delay line would take up far too
+/// much memory. The filters are allocated up front, so acquiring and releasing them doesn't
+/// allocate on the audio thread.
+#[derive(Debug, Clone, Default)]
+pub struct CombPool {
+    filters: Vec<CombFilter>,
+    /// The indices of the filters that aren't in use.
+    free: Vec<usize>,
+    sample_rate: f32,
+}
+
+impl CombPool {
+    /// Allocate every filter in the pool with room for the longest delay at `sample_rate`. All
+    /// filters are free afterwards.
+    pub fn initialize(&mut self, sample_rate: f32) {
+        if !crate::sample_rate_changed(&mut self.sample_rate, sample_rate) {
+            return;
+        }
+
+        let max_delay = (MAX_DELAY_MS / 1000.0 * sample_rate).ceil() as usize;
+        self.filters = vec![
+            CombFilter {
+                buffers: [vec![0.0; max_delay + 2], vec![0.0; max_delay + 2]],
+                write_index: 0,
+            };
+            NUM_COMB_FILTERS
+        ];
+        self.free = Vec::with_capacity(NUM_COMB_FILTERS);
+        self.reset();
+    }
+
+    /// Mark every comb filter as free. Voices holding on to a filter must drop their index.
+    pub fn reset(&mut self) {
+        self.free.clear();
+        self.free.extend((0..self.filters.len()).rev());
+    }
+
+    /// Take a cleared comb filter out of the pool, or return `None` if all of them are in use.
+    pub fn acquire(&mut self) -> Option<usize> {
+        let index = self.free.pop()?;
+        self.filters[index].reset();
+
+        Some(index)
+    }
+
+    /// Return a comb filter obtained through [`acquire()`][Self::acquire()] to the pool.
+    pub fn release(&mut self, index: usize) {
+        debug_assert!(!self.free.contains(&index));
+        self.free.push(index);
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> &mut CombFilter {
+        &mut self.filters[index]
+    }
+}
+
+impl CombFilter {
+    fn reset(&mut self) {
+        for buffer in &mut self.buffers {
+            buffer.fill(0.0);
+        }
+        self.write_index = 0;
+    }
+
+    /// Filter a stereo sample pair with a delay of `delay` samples. The output is scaled down by the
+    /// feedback amount so the resonant peaks stay at unity gain.
+    pub fn process(&mut self, samples: [f32; 2], delay: f32, feedback: f32) -> [f32; 2] {
+        let buffer_len = self.buffers[0].len();
+        let delay = delay.clamp(1.0, (buffer_len - 2) as f32);
+        let feedback = feedback.clamp(-MAX_FEEDBACK, MAX_FEEDBACK);
+
+        let read_position = (self.write_index + buffer_len) as f32 - delay;
+        let read_index = read_position as usize;
+        let read_fraction = read_position - read_index as f32;
+
+        let write_index = self.write_index;
+        let output = std::array::from_fn(|channel| {
+            let buffer = &mut self.buffers[channel];
+            let previous = buffer[read_index % buffer_len];
+            let next = buffer[(read_index + 1) % buffer_len];
+            let delayed = previous + (next - previous) * read_fraction;
+
+            let sample = samples[channel] + delayed * feedback;
+            buffer[write_index] = sample;
+
+            sample * (1.0 - feedback.abs())
+        });
+
+        self.write_index = (self.write_index + 1) % buffer_len;
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::TAU;
+
+    #[test]
+    fn resonant_peaks_sit_at_the_comb_frequency() {
+        // A 100 sample delay at 48 kHz puts the peaks at multiples of 480 Hz
+        let gain = |frequency: f32| {
+            let mut pool = CombPool::default();
+            pool.initialize(48000.0);
+            let index = pool.acquire().unwrap();
+            let comb = pool.get_mut(index);
+
+            (0..48000)
+                .map(|idx| {
+                    let sample = (idx as f32 * TAU * frequency / 48000.0).sin();
+                    comb.process([sample, sample], 100.0, 0.9)[0]
+                })
+                .skip(24000)
+                .fold(0.0f32, |peak, sample| peak.max(sample.abs()))
+        };
+
+        for peak in [480.0, 960.0, 1440.0] {
+            assert!((gain(peak) - 1.0).abs() < 1e-2, "{peak} Hz");
+        }
+        // Halfway between the peaks the feedback cancels out most of the signal
+        for notch in [720.0, 1200.0] {
+            assert!((gain(notch) - 0.1 / 1.9).abs() < 1e-2, "{notch} Hz");
+        }
+    }
+}
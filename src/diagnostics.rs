@@ -0,0 +1,82 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicU32, Ordering},
+};
+
+/// Voice statistics published by the audio thread, for the editor to display.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    active_voices: AtomicU32,
+    notes_per_second: AtomicU32,
+    steals_per_second: AtomicU32,
+}
+
+/// Counts voice events on the audio thread and publishes them to a [`Diagnostics`] once per
+/// second. This never allocates or locks.
+#[derive(Debug, Default)]
+pub struct DiagnosticsRecorder {
+    diagnostics: Arc<Diagnostics>,
+    /// The number of notes started in the current one second window.
+    notes: u32,
+    /// The number of notes that started on a voice that was still playing in the current window.
+    steals: u32,
+    /// The number of samples processed in the current window.
+    window_samples: u32,
+}
+
+impl Diagnostics {
+    /// The number of voices that were playing at the end of the last processed buffer, not
+    /// including stolen voices that are still fading out.
+    pub fn active_voices(&self) -> u32 {
+        self.active_voices.load(Ordering::Relaxed)
+    }
+
+    /// The number of notes started during the last full second.
+    pub fn notes_per_second(&self) -> u32 {
+        self.notes_per_second.load(Ordering::Relaxed)
+    }
+
+    /// The number of notes that had to take over a voice that was still playing during the last
+    /// full second.
+    pub fn steals_per_second(&self) -> u32 {
+        self.steals_per_second.load(Ordering::Relaxed)
+    }
+}
+
+impl DiagnosticsRecorder {
+    /// The shared statistics, for the editor to read from.
+    pub fn diagnostics(&self) -> Arc<Diagnostics> {
+        self.diagnostics.clone()
+    }
+
+    pub fn reset(&mut self) {
+        self.notes = 0;
+        self.steals = 0;
+        self.window_samples = 0;
+    }
+
+    pub fn note_started(&mut self, stolen: bool) {
+        self.notes += 1;
+        if stolen {
+            self.steals += 1;
+        }
+    }
+
+    /// Publish the statistics after processing `num_samples` samples.
+    pub fn process(&mut self, num_samples: usize, active_voices: usize, sample_rate: f32) {
+        self.diagnostics
+            .active_voices
+            .store(active_voices as u32, Ordering::Relaxed);
+
+        self.window_samples += num_samples as u32;
+        if self.window_samples as f32 >= sample_rate {
+            self.diagnostics
+                .notes_per_second
+                .store(self.notes, Ordering::Relaxed);
+            self.diagnostics
+                .steals_per_second
+                .store(self.steals, Ordering::Relaxed);
+            self.reset();
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::ops::Range;
+
+use crate::{MAX_BLOCK_SIZE, PolyModSynthParams, reverb::Reverb};
+
+/// The master effects chain. Every effect in here produces a fully wet signal, and the chain's
+/// output is blended with a dry copy of the input using the single effects mix control. This keeps
+/// the blending in one place instead of having every effect apply its own dry/wet gain.
+#[derive(Debug, Clone, Default)]
+pub struct EffectsBus {
+    reverb: Reverb,
+}
+
+impl EffectsBus {
+    /// Allocate the effects' buffers for `sample_rate`. This should be called from `initialize()`.
+    pub fn initialize(&mut self, sample_rate: f32) {
+        self.reverb.initialize(sample_rate);
+    }
+
+    pub fn reset(&mut self) {
+        self.reverb.reset();
+    }
+
+    /// Run the effects chain on `output[..][range]`. `range` may not be longer than
+    /// [`MAX_BLOCK_SIZE`].
+    pub fn process(
+        &mut self,
+        output: &mut [&mut [f32]],
+        range: Range<usize>,
+        params: &PolyModSynthParams,
+    ) {
+        let mix = params.effects_mix.value();
+
+        if mix == 0.0 {
+            return;
+        }
+
+        let block_len = range.len();
+        let mut dry = [[0.0; MAX_BLOCK_SIZE]; 2];
+        for (dry, channel) in dry.iter_mut().zip(output.iter()) {
+            dry[..block_len].copy_from_slice(&channel[range.clone()]);
+        }
+
+        self.reverb.process(
+            output,
+            range.clone(),
+            params.reverb_size.value(),
+            params.reverb_damping.value(),
+        );
+
+        for (dry, channel) in dry.iter().zip(output.iter_mut()) {
+            for (dry, sample) in dry.iter().zip(&mut channel[range.clone()]) {
+                *sample = dry * (1.0 - mix) + *sample * mix;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_host::float_param;
+
+    #[test]
+    fn zero_mix_leaves_the_signal_bit_exact() {
+        let input: Vec<f32> = (0..256).map(|idx| (idx as f32 * 0.1).sin()).collect();
+        let process = |mix: f32| {
+            let params = PolyModSynthParams {
+                effects_mix: float_param(mix),
+                ..PolyModSynthParams::default()
+            };
+            let mut effects = EffectsBus::default();
+            effects.initialize(48000.0);
+
+            let (mut left, mut right) = (input.clone(), input.clone());
+            for block_start in (0..input.len()).step_by(MAX_BLOCK_SIZE) {
+                effects.process(
+                    &mut [&mut left, &mut right],
+                    block_start..block_start + MAX_BLOCK_SIZE,
+                    &params,
+                );
+            }
+
+            [left, right]
+        };
+
+        assert_eq!(process(0.0), [input.clone(), input.clone()]);
+        assert_ne!(process(0.5)[0], input);
+    }
+}
@@ -0,0 +1,170 @@
+use std::{f32::consts::TAU, ops::Range};
+
+/// The bandwidth of the mid band.
+const MID_Q: f32 = 0.7;
+
+/// The slope of the shelving bands, where 1 is the steepest slope without an overshoot.
+const SHELF_SLOPE: f32 = 1.0;
+
+/// The settings for a single EQ band.
+#[derive(Debug, Clone, Copy)]
+pub struct Band {
+    pub frequency: f32,
+    pub gain_db: f32,
+}
+
+/// A three band master EQ with a low shelf, a mid peak, and a high shelf. Bands set to 0 dB are
+/// skipped entirely, so a flat EQ leaves the signal bit-exact.
+#[derive(Debug, Clone, Default)]
+pub struct Equalizer {
+    low: Biquad,
+    mid: Biquad,
+    high: Biquad,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Shape {
+    LowShelf,
+    Peak,
+    HighShelf,
+}
+
+/// A stereo biquad filter using the coefficients from the Audio EQ Cookbook, in transposed direct
+/// form II.
+#[derive(Debug, Clone, Default)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    state: [[f32; 2]; 2],
+}
+
+impl Equalizer {
+    pub fn reset(&mut self) {
+        for band in [&mut self.low, &mut self.mid, &mut self.high] {
+            band.state = [[0.0; 2]; 2];
+        }
+    }
+
+    /// Run the EQ on `output[..][range]`.
+    pub fn process(
+        &mut self,
+        output: &mut [&mut [f32]],
+        range: Range<usize>,
+        [low, mid, high]: [Band; 3],
+        sample_rate: f32,
+    ) {
+        for (filter, band, shape) in [
+            (&mut self.low, low, Shape::LowShelf),
+            (&mut self.mid, mid, Shape::Peak),
+            (&mut self.high, high, Shape::HighShelf),
+        ] {
+            // Flat bands also have their state cleared so they don't ring out old signal when
+            // they're turned back on
+            if band.gain_db == 0.0 {
+                filter.state = [[0.0; 2]; 2];
+                continue;
+            }
+
+            filter.set_coefficients(shape, band, sample_rate);
+            for (channel, state) in output.iter_mut().zip(filter.state.iter_mut()) {
+                for sample in &mut channel[range.clone()] {
+                    let input = *sample;
+                    let filtered = filter.b0 * input + state[0];
+                    state[0] = filter.b1 * input - filter.a1 * filtered + state[1];
+                    state[1] = filter.b2 * input - filter.a2 * filtered;
+                    *sample = filtered;
+                }
+            }
+        }
+    }
+}
+
+impl Biquad {
+    fn set_coefficients(&mut self, shape: Shape, band: Band, sample_rate: f32) {
+        let frequency = band.frequency.clamp(10.0, sample_rate * 0.49);
+        let a = 10.0f32.powf(band.gain_db / 40.0);
+        let omega = TAU * frequency / sample_rate;
+        let (sin, cos) = omega.sin_cos();
+
+        let (b0, b1, b2, a0, a1, a2) = match shape {
+            Shape::Peak => {
+                let alpha = sin / (2.0 * MID_Q);
+                (
+                    1.0 + alpha * a,
+                    -2.0 * cos,
+                    1.0 - alpha * a,
+                    1.0 + alpha / a,
+                    -2.0 * cos,
+                    1.0 - alpha / a,
+                )
+            }
+            Shape::LowShelf | Shape::HighShelf => {
+                let alpha = sin / 2.0 * ((a + 1.0 / a) * (1.0 / SHELF_SLOPE - 1.0) + 2.0).sqrt();
+                let shelf = 2.0 * a.sqrt() * alpha;
+                // The high shelf is the low shelf with the sign of the cosine terms flipped
+                let sign = if shape == Shape::LowShelf { 1.0 } else { -1.0 };
+                let cos = cos * sign;
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos) * sign,
+                    a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                    (a + 1.0) + (a - 1.0) * cos + shelf,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos) * sign,
+                    (a + 1.0) + (a - 1.0) * cos - shelf,
+                )
+            }
+        };
+
+        self.b0 = b0 / a0;
+        self.b1 = b1 / a0;
+        self.b2 = b2 / a0;
+        self.a1 = a1 / a0;
+        self.a2 = a2 / a0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn high_shelf_boosts_the_highs() {
+        let flat = Band {
+            frequency: 1000.0,
+            gain_db: 0.0,
+        };
+        let high_shelf = Band {
+            frequency: 2000.0,
+            gain_db: 6.0,
+        };
+        let gain = |frequency: f32| {
+            let input: Vec<f32> = (0..48000)
+                .map(|idx| (idx as f32 * TAU * frequency / 48000.0).sin())
+                .collect();
+            let (mut left, mut right) = (input.clone(), input.clone());
+            let mut eq = Equalizer::default();
+            for block_start in (0..input.len()).step_by(64) {
+                eq.process(
+                    &mut [&mut left, &mut right],
+                    block_start..block_start + 64,
+                    [flat, flat, high_shelf],
+                    48000.0,
+                );
+            }
+
+            let peak = |samples: &[f32]| {
+                samples[24000..]
+                    .iter()
+                    .fold(0.0f32, |peak, sample| peak.max(sample.abs()))
+            };
+            20.0 * (peak(&left) / peak(&input)).log10()
+        };
+
+        // Well above the corner frequency the shelf's full gain applies, and the lows are left alone
+        assert!((gain(12000.0) - 6.0).abs() < 0.1);
+        assert!(gain(100.0).abs() < 0.1);
+    }
+}
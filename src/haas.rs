@@ -0,0 +1,79 @@
+use std::ops::Range;
+
+/// The longest delay the widener can apply. Past this the delayed channel is heard as a separate
+/// echo instead of as width.
+pub const MAX_DELAY_MS: f32 = 30.0;
+
+/// A Haas-effect stereo widener. This delays the right channel by a few milliseconds, which makes
+/// the sound appear wider without changing the level of either channel. Summing the result to mono
+/// causes comb filtering, which shows up in the stereo correlation.
+#[derive(Debug, Clone, Default)]
+pub struct HaasWidener {
+    buffer: Vec<f32>,
+    write_index: usize,
+    /// Used to convert the delay time to samples.
+    sample_rate: f32,
+}
+
+impl HaasWidener {
+    /// Allocate a delay line long enough for the longest delay at `sample_rate`.
+    pub fn initialize(&mut self, sample_rate: f32) {
+        if !crate::sample_rate_changed(&mut self.sample_rate, sample_rate) {
+            return;
+        }
+
+        let max_delay = (MAX_DELAY_MS / 1000.0 * sample_rate).ceil() as usize;
+        self.buffer = vec![0.0; max_delay + 1];
+        self.write_index = 0;
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.write_index = 0;
+    }
+
+    /// Delay the right channel of `output[..][range]` by `delay_ms` milliseconds.
+    pub fn process(&mut self, output: &mut [&mut [f32]], range: Range<usize>, delay_ms: f32) {
+        let buffer_len = self.buffer.len();
+        if buffer_len == 0 {
+            return;
+        }
+
+        let delay = ((delay_ms / 1000.0 * self.sample_rate).round() as usize).min(buffer_len - 1);
+        for sample in &mut output[1][range] {
+            self.buffer[self.write_index] = *sample;
+            *sample = self.buffer[(self.write_index + buffer_len - delay) % buffer_len];
+
+            self.write_index += 1;
+            if self.write_index >= buffer_len {
+                self.write_index = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn right_channel_lags_by_the_delay() {
+        let mut haas = HaasWidener::default();
+        haas.initialize(48000.0);
+
+        // 10 ms at 48 kHz is 480 samples, which takes several blocks to come out
+        let input: Vec<f32> = (0..2048).map(|idx| ((idx * 7) % 13) as f32).collect();
+        let (mut left, mut right) = (input.clone(), input.clone());
+        for block_start in (0..input.len()).step_by(64) {
+            haas.process(
+                &mut [&mut left, &mut right],
+                block_start..block_start + 64,
+                10.0,
+            );
+        }
+
+        assert_eq!(left, input);
+        assert!(right[..480].iter().all(|&sample| sample == 0.0));
+        assert_eq!(right[480..], input[..input.len() - 480]);
+    }
+}
@@ -1,9 +1,38 @@
+use atomic_float::AtomicF32;
+use comb::CombPool;
+use diagnostics::{Diagnostics, DiagnosticsRecorder};
+use effects::EffectsBus;
+use eq::{Band, Equalizer};
+use haas::HaasWidener;
+use mono_safety::MonoSafety;
 use nih_plug::{
-    midi::control_change::{ALL_NOTES_OFF, POLY_MODE_ON, RESET_ALL_CONTROLLERS},
+    midi::control_change::{
+        ALL_NOTES_OFF, ALL_SOUND_OFF, EXPRESSION_CONTROLLER_MSB, POLY_MODE_ON,
+        RESET_ALL_CONTROLLERS, SOFT_PEDAL, SUSTENUTO,
+    },
     prelude::*,
     util::db_to_gain,
 };
-use std::{f32::consts::TAU, sync::Arc};
+use phaser::Phaser;
+use scope::{Scope, ScopeFrame};
+use std::{
+    f32::consts::TAU,
+    sync::{Arc, PoisonError, RwLock, atomic::Ordering},
+};
+use true_peak::TruePeakLimiter;
+
+mod comb;
+pub mod diagnostics;
+mod effects;
+mod eq;
+mod haas;
+mod mono_safety;
+mod phaser;
+mod reverb;
+pub mod scope;
+#[cfg(test)]
+mod test_host;
+mod true_peak;
 
 // ! This needs a lot of code cleanup; many comments are incorrect
 
@@ -11,9 +40,138 @@ use std::{f32::consts::TAU, sync::Arc};
 /// values to buffers since these values may need to be reused for multiple voices.
 const MAX_BLOCK_SIZE: usize = 64;
 
+/// Every (channel, note) pair gets its own voice, so these determine the size of the voice array.
+const NUM_CHANNELS: usize = 16;
+const NUM_NOTES: usize = 128;
+
+/// The number of stereo auxiliary outputs that MIDI channels can be routed to.
+const NUM_AUX_OUTPUTS: usize = 3;
+
+/// The names of the aux output ports, from "Aux 1" up. These are built at compile time since the
+/// audio IO layouts are constants.
+const AUX_OUTPUT_NAMES: [&str; NUM_AUX_OUTPUTS] = {
+    assert!(
+        NUM_AUX_OUTPUTS <= 9,
+        "aux output names only have room for a single digit"
+    );
+
+    static NAMES: [[u8; 5]; NUM_AUX_OUTPUTS] = {
+        let mut names = [*b"Aux 0"; NUM_AUX_OUTPUTS];
+        let mut idx = 0;
+        while idx < NUM_AUX_OUTPUTS {
+            names[idx][4] += idx as u8 + 1;
+            idx += 1;
+        }
+        names
+    };
+
+    let mut names = [""; NUM_AUX_OUTPUTS];
+    let mut idx = 0;
+    while idx < NUM_AUX_OUTPUTS {
+        names[idx] = match std::str::from_utf8(&NAMES[idx]) {
+            Ok(name) => name,
+            Err(_) => unreachable!(),
+        };
+        idx += 1;
+    }
+    names
+};
+
+/// The maximum number of stolen voices that can be fading out at the same time. Past this, stolen
+/// voices are cut off immediately.
+const MAX_FADING_VOICES: usize = 32;
+
+/// The voice drive is blended in with the dry signal up to this amount, so the saturation fades in
+/// gradually instead of jumping to a full tanh curve as soon as the drive leaves 0 dB.
+const DRIVE_FADE_IN_DB: f32 = 6.0;
+
+/// Expression (CC11) changes are smoothed over this time to avoid zipper noise.
+const EXPRESSION_SMOOTHING_MS: f32 = 10.0;
+
+/// The time constant of the pitch drift at the start of a note settling back to the note's pitch.
+const ONSET_DRIFT_MS: f32 = 20.0;
+
+/// The MIDI velocities of the dynamic markings from ppp to fff, as used by most notation software.
+const DYNAMICS_VELOCITIES: [u8; 8] = [16, 32, 48, 64, 80, 96, 112, 127];
+
+/// The names of the dynamic markings in [`DYNAMICS_VELOCITIES`].
+const DYNAMICS_MARKINGS: [&str; 8] = ["ppp", "pp", "p", "mp", "mf", "f", "ff", "fff"];
+
+/// The loudness difference between two neighbouring dynamic markings, used to show the velocity
+/// range as the span of markings it covers.
+const DYNAMICS_STEP_DB: f32 = 6.0;
+
+/// The length of a metronome click.
+#[cfg(debug_assertions)]
+const METRONOME_CLICK_MS: f32 = 10.0;
+
+/// The pitch of a metronome click.
+#[cfg(debug_assertions)]
+const METRONOME_FREQUENCY: f32 = 1000.0;
+
+/// The base note that plays every note at its own pitch (C4).
+const UNTRANSPOSED_BASE_NOTE: i32 = 60;
+
+/// Keyboard tracking is relative to this note (C4).
+const KEY_TRACKING_CENTER: u8 = 60;
+
+/// The equal loudness compensation never boosts a note by more than this, since the A-weighting
+/// curve drops off steeply towards the bottom of the MIDI note range.
+const MAX_EQUAL_LOUDNESS_BOOST_DB: f32 = 12.0;
+
 pub struct PolyModSynth {
     params: Arc<PolyModSynthParams>,
     voices: Vec<Voice>,
+    /// The indices of the active voices in `voices`, in no particular order. Rendering only iterates
+    /// over these instead of over the entire voice array. This has room for every voice, so it
+    /// doesn't allocate on the audio thread.
+    active_voices: Vec<usize>,
+    /// Whether the sostenuto pedal (CC66) is currently pressed, per channel.
+    sostenuto: [bool; NUM_CHANNELS],
+    /// Whether the soft pedal (CC67) is currently pressed, per channel.
+    soft_pedal: [bool; NUM_CHANNELS],
+    /// The pitch bend per channel, from -1 to 1.
+    pitch_bend: [f32; NUM_CHANNELS],
+    /// The expression controller (CC11) per channel, used when expression is applied per channel.
+    expression: [Smoother<f32>; NUM_CHANNELS],
+    /// The last expression controller value received on any channel, used when expression is
+    /// applied to every voice.
+    master_expression: Smoother<f32>,
+    /// Copies of stolen voices that are still fading out. This never grows past
+    /// [`MAX_FADING_VOICES`], so it doesn't allocate on the audio thread.
+    fading_voices: Vec<Voice>,
+    /// The comb filters voices borrow while they're playing.
+    comb_filters: CombPool,
+    phaser: Phaser,
+    effects: EffectsBus,
+    eq: Equalizer,
+    haas: HaasWidener,
+    mono_safety: MonoSafety,
+    /// One limiter for the main output, followed by one for each aux output.
+    true_peak_limiters: [TruePeakLimiter; NUM_AUX_OUTPUTS + 1],
+    /// The stereo correlation of the last processed buffer, from -1 (fully out of phase) to 1
+    /// (mono). This is published for the editor to display.
+    correlation: Arc<AtomicF32>,
+    /// Captures triggered frames of the output for an oscilloscope display.
+    scope: Scope,
+    /// Voice statistics for the editor.
+    diagnostics: DiagnosticsRecorder,
+    /// The phase of the reference tone, used instead of the voices when the reference tone is
+    /// enabled.
+    reference_phase: f32,
+    /// The number of samples since the current metronome click started, or `None` if no click is
+    /// playing.
+    #[cfg(debug_assertions)]
+    metronome_click: Option<u32>,
+    /// Whether the host's transport was playing during the last process call. Used to detect
+    /// playback starting for the reset on play option.
+    was_playing: bool,
+    /// The PRNG state used to pick each note's onset pitch drift. This is reseeded on reset so
+    /// renders are reproducible.
+    onset_drift_state: u32,
+    /// The gain of the fade in after the plugin is loaded or reset, going from 0 to 1 over the
+    /// declick time so leftover state can't cause a pop.
+    soft_start_gain: f32,
 }
 
 #[derive(Params)]
@@ -22,10 +180,236 @@ struct PolyModSynthParams {
     gain: FloatParam,
     #[id = "vrange"]
     velocity_range: FloatParam,
+    #[id = "vmode"]
+    velocity_mode: EnumParam<VelocityMode>,
+    #[id = "vinterp"]
+    velocity_interpretation: EnumParam<VelocityInterpretation>,
+    #[id = "vfloor"]
+    velocity_floor: FloatParam,
+    #[id = "softpdl"]
+    soft_pedal_attenuation: FloatParam,
+    #[id = "exprmode"]
+    expression_mode: EnumParam<ExpressionMode>,
+    #[id = "keylevel"]
+    key_to_level: FloatParam,
+    #[id = "eqloud"]
+    equal_loudness: BoolParam,
     #[id = "sine"]
     sine_wave: BoolParam,
+    #[id = "detune"]
+    detune: FloatParam,
+    #[id = "basenote"]
+    base_note: IntParam,
+    #[id = "drift"]
+    onset_drift: FloatParam,
+    #[id = "pbrange"]
+    pitch_bend_range: FloatParam,
+    #[id = "velsaw"]
+    velocity_to_saw: FloatParam,
+    #[id = "noise"]
+    noise_level: FloatParam,
+    #[id = "vdrive"]
+    voice_drive: FloatParam,
+    #[id = "combfb"]
+    comb_feedback: FloatParam,
+    #[id = "combratio"]
+    comb_ratio: FloatParam,
+    #[id = "startovr"]
+    start_phase_override: BoolParam,
+    #[id = "startphase"]
+    start_phase: FloatParam,
+    #[id = "stphase"]
+    stereo_phase: FloatParam,
+    #[id = "keypan"]
+    key_to_pan: FloatParam,
+    #[id = "rndpan"]
+    random_pan: FloatParam,
+    #[id = "rndpanseed"]
+    random_pan_seed: IntParam,
+    #[id = "monopan"]
+    mono_pan_compensation: BoolParam,
+    #[id = "reftone"]
+    reference_tone: BoolParam,
+    #[id = "refnote"]
+    reference_note: IntParam,
+    /// A click on every beat for checking the tempo sync, in place of the voices. This is a
+    /// debugging aid, so it only exists in debug builds.
+    #[cfg(debug_assertions)]
+    #[id = "metronome"]
+    metronome: BoolParam,
+    #[id = "pvmode"]
+    poly_volume_mode: EnumParam<PolyVolumeMode>,
+    #[id = "phstages"]
+    phaser_stages: IntParam,
+    #[id = "phrate"]
+    phaser_rate: FloatParam,
+    #[id = "phdepth"]
+    phaser_depth: FloatParam,
+    #[id = "phfb"]
+    phaser_feedback: FloatParam,
+    #[id = "phmix"]
+    phaser_mix: FloatParam,
+    #[id = "fxmix"]
+    effects_mix: FloatParam,
+    #[id = "rvbsize"]
+    reverb_size: FloatParam,
+    #[id = "rvbdamp"]
+    reverb_damping: FloatParam,
+    #[id = "eqlowgain"]
+    eq_low_gain: FloatParam,
+    #[id = "eqlowfreq"]
+    eq_low_frequency: FloatParam,
+    #[id = "eqmidgain"]
+    eq_mid_gain: FloatParam,
+    #[id = "eqmidfreq"]
+    eq_mid_frequency: FloatParam,
+    #[id = "eqhighgain"]
+    eq_high_gain: FloatParam,
+    #[id = "eqhighfreq"]
+    eq_high_frequency: FloatParam,
+    #[id = "haas"]
+    haas_delay: FloatParam,
+    #[id = "monosafe"]
+    mono_safety: BoolParam,
+    #[id = "monothr"]
+    mono_safety_threshold: FloatParam,
+    #[id = "tplimit"]
+    true_peak_limit: BoolParam,
+    #[id = "tpceil"]
+    true_peak_ceiling: FloatParam,
+    #[id = "tpattack"]
+    limiter_attack: FloatParam,
+    #[id = "tprelease"]
+    limiter_release: FloatParam,
+    #[id = "declick"]
+    declick_time: FloatParam,
+    #[id = "pdlstagger"]
+    pedal_release_stagger: FloatParam,
+    #[id = "noretrig"]
+    retrigger_free: BoolParam,
+    #[id = "drone"]
+    drone_mode: BoolParam,
+    #[id = "maxlen"]
+    max_note_length: FloatParam,
+    #[id = "scopetrig"]
+    scope_trigger_level: FloatParam,
+    #[id = "rstplay"]
+    reset_on_play: BoolParam,
+    #[id = "swap"]
+    swap_channels: BoolParam,
+    #[id = "invleft"]
+    invert_left: BoolParam,
+    #[id = "invright"]
+    invert_right: BoolParam,
+    #[nested(array, group = "Channel")]
+    channels: [ChannelParams; NUM_CHANNELS],
+
+    /// Breakpoints of the velocity response curve as `(input, output)` pairs sorted by input, used
+    /// to remap incoming velocities before they're converted to gain. An empty curve leaves the
+    /// velocities unchanged.
+    #[persist = "velocity-curve"]
+    velocity_curve: RwLock<Vec<(f32, f32)>>,
+    /// Per-note tuning offsets in cents on top of 12-TET, indexed by MIDI note number. Notes past
+    /// the end of the table aren't retuned, so an empty table is plain 12-TET.
+    #[persist = "tuning-table"]
+    tuning_table: RwLock<Vec<f32>>,
+}
+
+/// Offsets applied to every voice on a MIDI channel, for voicing channels differently in
+/// multi-timbral setups. The defaults leave the channel unchanged.
+#[derive(Params)]
+struct ChannelParams {
+    #[id = "transpose"]
+    transpose: IntParam,
+    #[id = "level"]
+    level: FloatParam,
+    #[id = "pan"]
+    pan: FloatParam,
+    /// The output the channel's voices are sent to. 0 is the main output, and higher values are the
+    /// auxiliary outputs.
+    #[id = "output"]
+    output: IntParam,
+}
+
+/// How the velocity range is placed around the note gain.
+#[derive(Enum, Debug, Clone, Copy, PartialEq)]
+enum VelocityMode {
+    /// Full velocity plays at the note gain, and lower velocities are attenuated by up to the
+    /// velocity range.
+    #[name = "Attenuate"]
+    Attenuate,
+    /// Zero velocity plays at the note gain, and higher velocities are boosted by up to the
+    /// velocity range.
+    #[name = "Boost"]
+    Boost,
+    /// The velocity range is centered around the note gain.
+    #[name = "Centered"]
+    Centered,
+}
+
+/// How incoming velocities are placed within the velocity range.
+#[derive(Enum, Debug, Clone, Copy, PartialEq)]
+enum VelocityInterpretation {
+    /// Velocity is already perceptual, so it's mapped linearly onto the decibel range.
+    #[name = "Perceptual"]
+    Perceptual,
+    /// Velocity is a linear amplitude, so it's converted to decibels before being placed within
+    /// the range. This makes the lower velocities fall off faster.
+    #[name = "Linear"]
+    Linear,
+    /// Velocity is read as a dynamic marking from ppp to fff, using the velocities notation
+    /// software commonly plays those markings at. Every step between two markings covers the same
+    /// part of the decibel range, and velocities below ppp play at ppp.
+    #[name = "Dynamics (ppp-fff)"]
+    Dynamics,
+}
+
+#[derive(Enum, Debug, Clone, Copy, PartialEq)]
+enum ExpressionMode {
+    /// The expression controller only affects the voices on the channel it was sent on.
+    #[name = "Per Channel"]
+    Channel,
+    /// The expression controller affects every voice, regardless of channel.
+    #[name = "Master"]
+    Master,
 }
 
+#[derive(Enum, Debug, Clone, Copy, PartialEq)]
+enum PolyVolumeMode {
+    /// Polyphonic volume is used as a plain gain multiplier.
+    #[name = "Linear"]
+    Linear,
+    /// Polyphonic volume follows the MIDI volume curve of `40 * log10(x)` dB.
+    #[name = "Decibel Curve"]
+    Decibels,
+}
+
+/// Why a velocity curve or tuning table passed to [`PolyModSynth::set_velocity_curve()`] or
+/// [`PolyModSynth::set_tuning_table()`] was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableError {
+    /// One of the values wasn't a number.
+    NotANumber,
+    /// The tuning table didn't have exactly one entry per MIDI note.
+    WrongLength(usize),
+}
+
+impl std::fmt::Display for TableError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TableError::NotANumber => write!(f, "the table contains a value that isn't a number"),
+            TableError::WrongLength(len) => {
+                write!(
+                    f,
+                    "the tuning table has {len} entries instead of {NUM_NOTES}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for TableError {}
+
 #[derive(Debug, Clone)]
 struct Voice {
     active: bool,
@@ -34,30 +418,101 @@ struct Voice {
     voice_id: Option<i32>,
     frequency: f32,
     velocity: f32,
+    /// The velocity the note was started with. Unlike `velocity` this isn't changed by polyphonic
+    /// pressure.
+    note_on_velocity: f32,
     pan: f32,
     gain: Option<f32>,
     phase: f32,
+    /// The right channel runs its own phase accumulator, offset from `phase` by the stereo phase
+    /// offset at the start of the note.
+    right_phase: f32,
+    /// The waveform this voice is rendering. When the waveform parameter changes while the voice is
+    /// playing, it fades over from `previous_sine_wave`, with `waveform_crossfade` going from 1 to 0.
+    sine_wave: bool,
+    previous_sine_wave: bool,
+    waveform_crossfade: f32,
+    /// Set for stolen voices in `fading_voices`, going from 1 to 0 over the declick time.
+    fade_out: Option<f32>,
+    /// Set when the sostenuto pedal was pressed while this note was held. NoteOffs for the note
+    /// then only set `release_pending`, and the note is stopped once the pedal is lifted.
+    sostenuto_held: bool,
+    release_pending: bool,
+    /// The attenuation from the soft pedal, captured when the note starts.
+    soft_pedal_gain: f32,
+    /// How many samples the note has been playing for, used to enforce the maximum note length.
+    age: u32,
+    /// The state of the voice's white noise generator. This is reseeded when the note starts so
+    /// renders are reproducible.
+    noise_state: u32,
+    /// The index of the comb filter in the comb pool this voice is using, if it got one.
+    comb: Option<usize>,
+    /// The voice's current pitch offset in cents from the onset drift, decaying towards zero.
+    onset_drift: f32,
+    /// The `age` at which the voice should be stopped, used to stagger releases when the
+    /// sostenuto pedal is lifted.
+    release_at: Option<u32>,
+    /// The voice's random pan position from -1 to 1, scaled by the random pan amount.
+    random_pan: f32,
 }
 
 impl Default for PolyModSynth {
     fn default() -> Self {
         Self {
             params: Arc::new(PolyModSynthParams::default()),
-            voices: (0..=16)
+            voices: (0..NUM_CHANNELS as u8)
                 .flat_map(|channel| {
-                    (0..=127).map(move |note| Voice {
+                    (0..NUM_NOTES as u8).map(move |note| Voice {
                         active: false,
                         note,
                         channel,
                         voice_id: None,
                         frequency: util::midi_note_to_freq(note),
                         velocity: 0.0,
+                        note_on_velocity: 0.0,
                         pan: 0.0,
                         gain: None,
                         phase: 0.0,
+                        right_phase: 0.0,
+                        sine_wave: true,
+                        previous_sine_wave: true,
+                        waveform_crossfade: 0.0,
+                        fade_out: None,
+                        sostenuto_held: false,
+                        release_pending: false,
+                        soft_pedal_gain: 1.0,
+                        age: 0,
+                        noise_state: noise_seed(0),
+                        comb: None,
+                        onset_drift: 0.0,
+                        release_at: None,
+                        random_pan: 0.0,
                     })
                 })
                 .collect(),
+            active_voices: Vec::with_capacity(NUM_CHANNELS * NUM_NOTES),
+            sostenuto: [false; NUM_CHANNELS],
+            soft_pedal: [false; NUM_CHANNELS],
+            pitch_bend: [0.0; NUM_CHANNELS],
+            expression: std::array::from_fn(|_| expression_smoother()),
+            master_expression: expression_smoother(),
+            fading_voices: Vec::with_capacity(MAX_FADING_VOICES),
+            comb_filters: CombPool::default(),
+            phaser: Phaser::default(),
+            effects: EffectsBus::default(),
+            eq: Equalizer::default(),
+            haas: HaasWidener::default(),
+            mono_safety: MonoSafety::default(),
+            true_peak_limiters: Default::default(),
+            correlation: Arc::new(AtomicF32::new(1.0)),
+            scope: Scope::default(),
+            diagnostics: DiagnosticsRecorder::default(),
+            reference_phase: 0.0,
+            #[cfg(debug_assertions)]
+            metronome_click: None,
+            was_playing: false,
+            onset_drift_state: onset_drift_seed(),
+            soft_start_gain: 0.0,
         }
     }
 }
@@ -70,8 +525,8 @@ impl Default for PolyModSynthParams {
                 1.0 / 128.0,
                 FloatRange::Skewed {
                     min: util::db_to_gain(-100.0),
-                    max: util::db_to_gain(0.0),
-                    factor: FloatRange::gain_skew_factor(-100.0, 0.0),
+                    max: util::db_to_gain(12.0),
+                    factor: FloatRange::gain_skew_factor(-100.0, 12.0),
                 },
             )
             .with_unit(" dB")
@@ -85,13 +540,537 @@ impl Default for PolyModSynthParams {
                     max: 100.0,
                 },
             )
+            .with_value_to_string(Arc::new(velocity_range_to_string))
+            .with_string_to_value(Arc::new(string_to_velocity_range)),
+            velocity_mode: EnumParam::new("Velocity Mode", VelocityMode::Attenuate),
+            velocity_interpretation: EnumParam::new(
+                "Velocity Interpretation",
+                VelocityInterpretation::Perceptual,
+            ),
+            velocity_floor: FloatParam::new(
+                "Velocity Floor",
+                -100.0,
+                FloatRange::Linear {
+                    min: -100.0,
+                    max: 0.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            soft_pedal_attenuation: FloatParam::new(
+                "Soft Pedal Attenuation",
+                6.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 24.0,
+                },
+            )
             .with_unit(" dB")
             .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            expression_mode: EnumParam::new("Expression Mode", ExpressionMode::Channel),
+            key_to_level: FloatParam::new(
+                "Key Level Tracking",
+                0.0,
+                FloatRange::Linear {
+                    min: -12.0,
+                    max: 12.0,
+                },
+            )
+            .with_unit(" dB/oct")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            equal_loudness: BoolParam::new("Equal Loudness Compensation", false),
             sine_wave: BoolParam::new("Generate Sine Wave Output", true),
+            detune: FloatParam::new(
+                "Detune",
+                0.0,
+                FloatRange::Linear {
+                    min: -50.0,
+                    max: 50.0,
+                },
+            )
+            .with_unit(" cents")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            // Every note is transposed by the distance between this note and C4, so C5 plays
+            // everything an octave up
+            base_note: IntParam::new(
+                "Base Note",
+                UNTRANSPOSED_BASE_NOTE,
+                IntRange::Linear { min: 36, max: 84 },
+            )
+            .with_value_to_string(formatters::v2s_i32_note_formatter())
+            .with_string_to_value(formatters::s2v_i32_note_formatter()),
+            // This is kept to a small range since the drift is meant to be subtle
+            onset_drift: FloatParam::new(
+                "Onset Pitch Drift",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 25.0,
+                },
+            )
+            .with_unit(" cents")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            // This is continuous so the bend range can be set to microtonal intervals
+            pitch_bend_range: FloatParam::new(
+                "Pitch Bend Range",
+                2.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 48.0,
+                },
+            )
+            .with_unit(" st")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            // Louder notes are crossfaded further towards a sawtooth wave, up to this amount at full
+            // velocity
+            velocity_to_saw: FloatParam::new(
+                "Velocity to Saw Mix",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            noise_level: FloatParam::new(
+                "Noise Level",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            // The drive comes before the comb filter, so the resonances get fed the added
+            // harmonics. Zero drive bypasses the saturation.
+            voice_drive: FloatParam::new(
+                "Voice Drive",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 24.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            // Zero feedback bypasses the comb filter
+            comb_feedback: FloatParam::new(
+                "Comb Feedback",
+                0.0,
+                FloatRange::Linear {
+                    min: -comb::MAX_FEEDBACK,
+                    max: comb::MAX_FEEDBACK,
+                },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            // The comb filter's pitch relative to the note's pitch
+            comb_ratio: FloatParam::new(
+                "Comb Pitch Ratio",
+                1.0,
+                FloatRange::Skewed {
+                    min: 0.25,
+                    max: 8.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_rounded(3)),
+            // By default every waveform starts at a phase where it crosses zero
+            start_phase_override: BoolParam::new("Override Start Phase", false),
+            start_phase: FloatParam::new(
+                "Start Phase",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 360.0,
+                },
+            )
+            .with_unit("°")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            // This is capped at 90 degrees so summing the channels to mono never cancels out
+            stereo_phase: FloatParam::new(
+                "Stereo Phase Offset",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 90.0,
+                },
+            )
+            .with_unit("°")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            // Notes above the key tracking center are panned to the right and notes below it to the
+            // left, like the layout of a piano from the player's perspective
+            key_to_pan: FloatParam::new(
+                "Key Pan Tracking",
+                0.0,
+                FloatRange::Linear {
+                    min: -0.5,
+                    max: 0.5,
+                },
+            )
+            .with_unit("%/oct")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            random_pan: FloatParam::new(
+                "Random Pan",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            // The random pan positions only depend on this seed and the note, so renders are
+            // reproducible
+            random_pan_seed: IntParam::new(
+                "Random Pan Seed",
+                0,
+                IntRange::Linear { min: 0, max: 65535 },
+            ),
+            mono_pan_compensation: BoolParam::new("Mono Sum Pan Compensation", false),
+            reference_tone: BoolParam::new("Reference Tone", false),
+            reference_note: IntParam::new(
+                "Reference Note",
+                69,
+                IntRange::Linear { min: 0, max: 127 },
+            )
+            .with_value_to_string(formatters::v2s_i32_note_formatter())
+            .with_string_to_value(formatters::s2v_i32_note_formatter()),
+            #[cfg(debug_assertions)]
+            metronome: BoolParam::new("Metronome", false),
+            poly_volume_mode: EnumParam::new("Poly Volume Mode", PolyVolumeMode::Linear),
+            phaser_stages: IntParam::new(
+                "Phaser Stages",
+                4,
+                IntRange::Linear {
+                    min: 1,
+                    max: phaser::MAX_STAGES as i32,
+                },
+            ),
+            phaser_rate: FloatParam::new(
+                "Phaser Rate",
+                0.5,
+                FloatRange::Skewed {
+                    min: 0.01,
+                    max: 10.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            phaser_depth: FloatParam::new(
+                "Phaser Depth",
+                0.5,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            phaser_feedback: FloatParam::new(
+                "Phaser Feedback",
+                0.0,
+                FloatRange::Linear {
+                    min: -0.9,
+                    max: 0.9,
+                },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            // Zero mix bypasses the phaser
+            phaser_mix: FloatParam::new(
+                "Phaser Mix",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            effects_mix: FloatParam::new(
+                "Effects Mix",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            reverb_size: FloatParam::new(
+                "Reverb Size",
+                0.5,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            reverb_damping: FloatParam::new(
+                "Reverb Damping",
+                0.5,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            eq_low_gain: FloatParam::new(
+                "EQ Low Gain",
+                0.0,
+                FloatRange::Linear {
+                    min: -18.0,
+                    max: 18.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            eq_low_frequency: FloatParam::new(
+                "EQ Low Frequency",
+                150.0,
+                FloatRange::Skewed {
+                    min: 20.0,
+                    max: 20000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            eq_mid_gain: FloatParam::new(
+                "EQ Mid Gain",
+                0.0,
+                FloatRange::Linear {
+                    min: -18.0,
+                    max: 18.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            eq_mid_frequency: FloatParam::new(
+                "EQ Mid Frequency",
+                1000.0,
+                FloatRange::Skewed {
+                    min: 20.0,
+                    max: 20000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            eq_high_gain: FloatParam::new(
+                "EQ High Gain",
+                0.0,
+                FloatRange::Linear {
+                    min: -18.0,
+                    max: 18.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            eq_high_frequency: FloatParam::new(
+                "EQ High Frequency",
+                6000.0,
+                FloatRange::Skewed {
+                    min: 20.0,
+                    max: 20000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            haas_delay: FloatParam::new(
+                "Haas Delay",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: haas::MAX_DELAY_MS,
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            mono_safety: BoolParam::new("Mono Safety", false),
+            mono_safety_threshold: FloatParam::new(
+                "Mono Safety Threshold",
+                0.0,
+                FloatRange::Linear {
+                    min: -1.0,
+                    max: 1.0,
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            true_peak_limit: BoolParam::new("True Peak Limiter", false),
+            true_peak_ceiling: FloatParam::new(
+                "True Peak Ceiling",
+                -1.0,
+                FloatRange::Linear {
+                    min: -12.0,
+                    max: 0.0,
+                },
+            )
+            .with_unit(" dBTP")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            limiter_attack: FloatParam::new(
+                "Limiter Attack",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 10.0,
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            limiter_release: FloatParam::new(
+                "Limiter Release",
+                50.0,
+                FloatRange::Skewed {
+                    min: 1.0,
+                    max: 1000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            // When a new note starts on a voice that's still playing, the old sound fades out over
+            // this time instead of being cut off. Switching the waveform mid-note crossfades over
+            // this time, and the output also fades in over this time after a reset.
+            declick_time: FloatParam::new(
+                "Declick Time",
+                5.0,
+                FloatRange::Skewed {
+                    min: 0.5,
+                    max: 20.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            // When the sostenuto pedal is lifted, the notes it released are spread out over this
+            // time, from the lowest to the highest note
+            pedal_release_stagger: FloatParam::new(
+                "Pedal Release Stagger",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 100.0,
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            retrigger_free: BoolParam::new("Retrigger-Free Repeated Notes", false),
+            drone_mode: BoolParam::new("Drone Mode", false),
+            // Zero means notes can be held indefinitely
+            max_note_length: FloatParam::new(
+                "Max Note Length",
+                0.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 600.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" s")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            scope_trigger_level: FloatParam::new(
+                "Scope Trigger Level",
+                0.0,
+                FloatRange::Linear {
+                    min: -1.0,
+                    max: 1.0,
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            reset_on_play: BoolParam::new("Reset Phase on Play", false),
+            swap_channels: BoolParam::new("Swap Left/Right", false),
+            invert_left: BoolParam::new("Invert Left", false),
+            invert_right: BoolParam::new("Invert Right", false),
+            channels: Default::default(),
+            velocity_curve: RwLock::new(Vec::new()),
+            tuning_table: RwLock::new(Vec::new()),
+        }
+    }
+}
+
+impl Default for ChannelParams {
+    fn default() -> Self {
+        Self {
+            transpose: IntParam::new(
+                "Channel Transpose",
+                0,
+                IntRange::Linear { min: -48, max: 48 },
+            )
+            .with_unit(" st"),
+            level: FloatParam::new(
+                "Channel Level",
+                0.0,
+                FloatRange::Linear {
+                    min: -24.0,
+                    max: 24.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            pan: FloatParam::new(
+                "Channel Pan",
+                0.0,
+                FloatRange::Linear {
+                    min: -1.0,
+                    max: 1.0,
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_panning())
+            .with_string_to_value(formatters::s2v_f32_panning()),
+            output: IntParam::new(
+                "Channel Output",
+                0,
+                IntRange::Linear {
+                    min: 0,
+                    max: NUM_AUX_OUTPUTS as i32,
+                },
+            )
+            .with_value_to_string(Arc::new(|value| match value {
+                0 => String::from("Main"),
+                n => format!("Aux {n}"),
+            })),
         }
     }
 }
 
+/// The parts of the host's [`ProcessContext`] that are used while processing. Processing goes
+/// through this instead of the context itself so the tests can run the synth with a scripted host,
+/// since NIH-plug's [`Transport`] can't be constructed outside of NIH-plug.
+trait ProcessHost {
+    fn sample_rate(&self) -> f32;
+    fn playing(&self) -> bool;
+    /// Only the metronome needs the tempo and song position.
+    #[cfg(debug_assertions)]
+    fn tempo(&self) -> Option<f64>;
+    #[cfg(debug_assertions)]
+    fn pos_beats(&self) -> Option<f64>;
+    fn next_event(&mut self) -> Option<PluginNoteEvent<PolyModSynth>>;
+    fn send_event(&mut self, event: PluginNoteEvent<PolyModSynth>);
+}
+
+impl<C: ProcessContext<PolyModSynth>> ProcessHost for C {
+    fn sample_rate(&self) -> f32 {
+        self.transport().sample_rate
+    }
+
+    fn playing(&self) -> bool {
+        self.transport().playing
+    }
+
+    #[cfg(debug_assertions)]
+    fn tempo(&self) -> Option<f64> {
+        self.transport().tempo
+    }
+
+    #[cfg(debug_assertions)]
+    fn pos_beats(&self) -> Option<f64> {
+        self.transport().pos_beats()
+    }
+
+    fn next_event(&mut self) -> Option<PluginNoteEvent<PolyModSynth>> {
+        ProcessContext::next_event(self)
+    }
+
+    fn send_event(&mut self, event: PluginNoteEvent<PolyModSynth>) {
+        ProcessContext::send_event(self, event)
+    }
+}
+
 impl Plugin for PolyModSynth {
     const NAME: &'static str = "Basic Synth";
     const VENDOR: &'static str = "transkatgirl";
@@ -100,11 +1079,25 @@ impl Plugin for PolyModSynth {
 
     const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
-    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[AudioIOLayout {
-        main_input_channels: NonZeroU32::new(2),
-        main_output_channels: NonZeroU32::new(2),
-        ..AudioIOLayout::const_default()
-    }];
+    // Hosts that don't support auxiliary outputs will pick the second layout, in which case every
+    // channel plays through the main output
+    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(2),
+            main_output_channels: NonZeroU32::new(2),
+            aux_output_ports: &[new_nonzero_u32(2); NUM_AUX_OUTPUTS],
+            names: PortNames {
+                aux_outputs: &AUX_OUTPUT_NAMES,
+                ..PortNames::const_default()
+            },
+            ..AudioIOLayout::const_default()
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(2),
+            main_output_channels: NonZeroU32::new(2),
+            ..AudioIOLayout::const_default()
+        },
+    ];
 
     const MIDI_INPUT: MidiConfig = MidiConfig::MidiCCs;
     const SAMPLE_ACCURATE_AUTOMATION: bool = true;
@@ -116,32 +1109,182 @@ impl Plugin for PolyModSynth {
         self.params.clone()
     }
 
+    fn initialize(
+        &mut self,
+        audio_io_layout: &AudioIOLayout,
+        buffer_config: &BufferConfig,
+        _context: &mut impl InitContext<Self>,
+    ) -> bool {
+        // Rendering always writes to the first two output channels, so we'll refuse layouts that
+        // don't have them instead of panicking in `process()`
+        if audio_io_layout
+            .main_output_channels
+            .is_none_or(|channels| channels.get() < 2)
+        {
+            return false;
+        }
+
+        self.allocate(buffer_config.sample_rate);
+
+        true
+    }
+
     // If the synth as a variable number of voices, you will need to call
     // `context.set_current_voice_capacity()` in `initialize()` and in `process()` (when the
     // capacity changes) to inform the host about this.
     fn reset(&mut self) {
         for voice in &mut self.voices {
             voice.active = false;
+            voice.comb = None;
+        }
+        self.active_voices.clear();
+        self.fading_voices.clear();
+        self.comb_filters.reset();
+        self.sostenuto = [false; NUM_CHANNELS];
+        self.soft_pedal = [false; NUM_CHANNELS];
+        self.pitch_bend = [0.0; NUM_CHANNELS];
+        for expression in &self.expression {
+            expression.reset(1.0);
+        }
+        self.master_expression.reset(1.0);
+        self.reference_phase = 0.0;
+        #[cfg(debug_assertions)]
+        {
+            self.metronome_click = None;
         }
+        self.onset_drift_state = onset_drift_seed();
+        self.soft_start_gain = 0.0;
+        self.phaser.reset();
+        self.effects.reset();
+        self.eq.reset();
+        self.haas.reset();
+        self.mono_safety.reset();
+        for limiter in &mut self.true_peak_limiters {
+            limiter.reset();
+        }
+        self.scope.reset();
+        self.diagnostics.reset();
     }
 
     fn process(
         &mut self,
         buffer: &mut Buffer,
-        _aux: &mut AuxiliaryBuffers,
+        aux: &mut AuxiliaryBuffers,
         context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
+        let mut aux_outputs: [&mut [&mut [f32]]; NUM_AUX_OUTPUTS] = Default::default();
+        for (aux_outputs, aux_output) in aux_outputs.iter_mut().zip(aux.outputs.iter_mut()) {
+            *aux_outputs = aux_output.as_slice();
+        }
+        self.process_buffer(buffer.as_slice(), &mut aux_outputs, context);
+
+        ProcessStatus::Normal
+    }
+}
+
+impl PolyModSynth {
+    /// Allocate everything that depends on the sample rate. The effects' delay lines are allocated
+    /// here instead of on the audio thread.
+    fn allocate(&mut self, sample_rate: f32) {
+        self.effects.initialize(sample_rate);
+        self.haas.initialize(sample_rate);
+        self.comb_filters.initialize(sample_rate);
+    }
+
+    /// Render the stereo `output` and `aux_outputs` while handling the host's events. Aux outputs
+    /// with fewer than two channels are skipped, and their voices play through the main output.
+    fn process_buffer(
+        &mut self,
+        output: &mut [&mut [f32]],
+        aux_outputs: &mut [&mut [&mut [f32]]],
+        context: &mut impl ProcessHost,
+    ) {
         // NIH-plug has a block-splitting adapter for `Buffer`. While this works great for effect
         // plugins, for polyphonic synths the block size should be `min(MAX_BLOCK_SIZE,
         // num_remaining_samples, next_event_idx - block_start_idx)`. Because blocks also need to be
         // split on note events, it's easier to work with raw audio here and to do the splitting by
         // hand.
-        let num_samples = buffer.samples();
-        let sample_rate = context.transport().sample_rate;
-        let output = buffer.as_slice();
+        let num_samples = output[0].len();
+        let sample_rate = context.sample_rate();
 
         let sine_wave = self.params.sine_wave.value();
+        let declick_step = 1.0 / (self.params.declick_time.value() / 1000.0 * sample_rate);
         let velocity_range = self.params.velocity_range.value();
+        let (velocity_min_db, velocity_max_db) = match self.params.velocity_mode.value() {
+            VelocityMode::Attenuate => (-velocity_range, 0.0),
+            VelocityMode::Boost => (0.0, velocity_range),
+            VelocityMode::Centered => (-velocity_range / 2.0, velocity_range / 2.0),
+        };
+        let velocity_interpretation = self.params.velocity_interpretation.value();
+        let velocity_floor = db_to_gain(self.params.velocity_floor.value());
+        let key_to_level = self.params.key_to_level.value();
+        let equal_loudness = self.params.equal_loudness.value();
+        let mono_pan_compensation = self.params.mono_pan_compensation.value();
+        let key_to_pan = self.params.key_to_pan.value();
+        let random_pan_amount = self.params.random_pan.value();
+        let expression_mode = self.params.expression_mode.value();
+        let frequency_multiplier = cents_to_ratio(
+            self.params.detune.value()
+                + (self.params.base_note.value() - UNTRANSPOSED_BASE_NOTE) as f32 * 100.0,
+        );
+        let noise_level = self.params.noise_level.value();
+        let voice_drive = self.params.voice_drive.value();
+        let voice_drive_gain = db_to_gain(voice_drive);
+        let voice_drive_mix = (voice_drive / DRIVE_FADE_IN_DB).min(1.0);
+        let velocity_to_saw = self.params.velocity_to_saw.value();
+        let comb_feedback = self.params.comb_feedback.value();
+        let comb_ratio = self.params.comb_ratio.value();
+        let onset_drift_decay = (-1.0 / (ONSET_DRIFT_MS / 1000.0 * sample_rate)).exp();
+        let pitch_bend_range = self.params.pitch_bend_range.value();
+        let reference_tone = self.params.reference_tone.value();
+        let reference_frequency = util::midi_note_to_freq(self.params.reference_note.value() as u8);
+        #[cfg(debug_assertions)]
+        let metronome = self.params.metronome.value();
+        #[cfg(not(debug_assertions))]
+        let metronome = false;
+        let poly_volume_mode = self.params.poly_volume_mode.value();
+        let eq_bands = [
+            (&self.params.eq_low_gain, &self.params.eq_low_frequency),
+            (&self.params.eq_mid_gain, &self.params.eq_mid_frequency),
+            (&self.params.eq_high_gain, &self.params.eq_high_frequency),
+        ]
+        .map(|(gain, frequency)| Band {
+            frequency: frequency.value(),
+            gain_db: gain.value(),
+        });
+        let haas_delay = self.params.haas_delay.value();
+        let mono_safety = self.params.mono_safety.value();
+        let mono_safety_threshold = self.params.mono_safety_threshold.value();
+        let true_peak_limit = self.params.true_peak_limit.value();
+        let true_peak_ceiling = db_to_gain(self.params.true_peak_ceiling.value());
+        let limiter_attack = self.params.limiter_attack.value();
+        let limiter_release = self.params.limiter_release.value();
+        let max_note_samples = (self.params.max_note_length.value() * sample_rate) as u32;
+
+        // Resetting the oscillators when playback starts makes renders of the same project
+        // reproducible, since the phases no longer depend on what was played before
+        let playing = context.playing();
+        if playing && !self.was_playing && self.params.reset_on_play.value() {
+            let stereo_phase = self.params.stereo_phase.value() / 360.0;
+            for &index in &self.active_voices {
+                let start_phase = self.start_phase(self.voices[index].sine_wave);
+                let voice = &mut self.voices[index];
+                voice.phase = start_phase;
+                voice.right_phase = (start_phase + stereo_phase).fract();
+            }
+            self.reference_phase = 0.0;
+        }
+        self.was_playing = playing;
+
+        // The metronome clicks on every beat while the transport is playing. This is the beat
+        // position at the start of the buffer and the number of beats per sample.
+        #[cfg(debug_assertions)]
+        let metronome_beats = match (playing, context.pos_beats(), context.tempo()) {
+            (true, Some(pos_beats), Some(tempo)) => {
+                Some((pos_beats, tempo / 60.0 / sample_rate as f64))
+            }
+            _ => None,
+        };
 
         let mut next_event = context.next_event();
         let mut block_start: usize = 0;
@@ -167,9 +1310,28 @@ impl Plugin for PolyModSynth {
                                 note,
                                 velocity,
                             } => {
-                                let voice =
-                                    self.start_voice(context, timing, channel, note, voice_id);
-                                voice.velocity = velocity;
+                                // MIDI running status sends NoteOffs as NoteOns with zero velocity
+                                if velocity == 0.0 {
+                                    self.release_voice(context, timing, channel, note);
+                                } else {
+                                    let velocity = self.map_velocity(velocity);
+                                    let voice = if self.params.retrigger_free.value()
+                                        && self
+                                            .voice_mut(channel, note)
+                                            .is_some_and(|voice| voice.active)
+                                    {
+                                        self.continue_voice(
+                                            context, timing, channel, note, voice_id,
+                                        )
+                                    } else {
+                                        self.start_voice(context, timing, channel, note, voice_id)
+                                    };
+
+                                    if let Some(voice) = voice {
+                                        voice.velocity = velocity;
+                                        voice.note_on_velocity = velocity;
+                                    }
+                                }
                             }
                             NoteEvent::PolyPressure {
                                 timing: _,
@@ -178,9 +1340,10 @@ impl Plugin for PolyModSynth {
                                 note,
                                 pressure,
                             } => {
-                                let voice =
-                                    &mut self.voices[(channel as usize * 128) + note as usize];
-                                voice.velocity = pressure;
+                                let pressure = self.map_velocity(pressure);
+                                if let Some(voice) = self.voice_mut(channel, note) {
+                                    voice.velocity = pressure;
+                                }
                             }
                             NoteEvent::PolyVolume {
                                 timing: _,
@@ -189,9 +1352,9 @@ impl Plugin for PolyModSynth {
                                 note,
                                 gain,
                             } => {
-                                let voice =
-                                    &mut self.voices[(channel as usize * 128) + note as usize];
-                                voice.gain = Some(gain);
+                                if let Some(voice) = self.voice_mut(channel, note) {
+                                    voice.gain = Some(gain);
+                                }
                             }
                             NoteEvent::PolyPan {
                                 timing: _,
@@ -200,9 +1363,9 @@ impl Plugin for PolyModSynth {
                                 note,
                                 pan,
                             } => {
-                                let voice =
-                                    &mut self.voices[(channel as usize * 128) + note as usize];
-                                voice.pan = pan;
+                                if let Some(voice) = self.voice_mut(channel, note) {
+                                    voice.pan = pan;
+                                }
                             }
                             NoteEvent::NoteOff {
                                 timing,
@@ -211,7 +1374,7 @@ impl Plugin for PolyModSynth {
                                 note,
                                 velocity: _,
                             } => {
-                                self.stop_voices(context, timing, channel, note);
+                                self.release_voice(context, timing, channel, note);
                             }
                             NoteEvent::Choke {
                                 timing,
@@ -221,21 +1384,53 @@ impl Plugin for PolyModSynth {
                             } => {
                                 self.stop_voices(context, timing, channel, note);
                             }
+                            NoteEvent::MidiPitchBend {
+                                timing: _,
+                                channel,
+                                value,
+                            } => {
+                                if let Some(pitch_bend) = self.pitch_bend.get_mut(channel as usize)
+                                {
+                                    *pitch_bend = (value * 2.0 - 1.0).clamp(-1.0, 1.0);
+                                }
+                            }
                             NoteEvent::MidiCC {
                                 timing,
                                 channel,
                                 cc,
-                                value: _,
-                            } => {
-                                if cc == RESET_ALL_CONTROLLERS
-                                    || cc == POLY_MODE_ON
-                                    || cc == ALL_NOTES_OFF
-                                {
-                                    for note in 0..=127 {
+                                value,
+                            } => match cc {
+                                ALL_SOUND_OFF
+                                | RESET_ALL_CONTROLLERS
+                                | POLY_MODE_ON
+                                | ALL_NOTES_OFF => {
+                                    if cc == RESET_ALL_CONTROLLERS {
+                                        self.reset_controllers(context, timing, channel);
+                                    }
+
+                                    for note in 0..NUM_NOTES as u8 {
                                         self.stop_voices(context, timing, channel, note);
                                     }
                                 }
-                            }
+                                SUSTENUTO => {
+                                    self.set_sostenuto(context, timing, channel, value >= 0.5);
+                                }
+                                EXPRESSION_CONTROLLER_MSB => {
+                                    if let Some(expression) = self.expression.get(channel as usize)
+                                    {
+                                        expression.set_target(sample_rate, value);
+                                    }
+                                    self.master_expression.set_target(sample_rate, value);
+                                }
+                                SOFT_PEDAL => {
+                                    if let Some(soft_pedal) =
+                                        self.soft_pedal.get_mut(channel as usize)
+                                    {
+                                        *soft_pedal = value >= 0.5;
+                                    }
+                                }
+                                _ => (),
+                            },
                             _ => (),
                         };
 
@@ -251,44 +1446,306 @@ impl Plugin for PolyModSynth {
                 }
             }
 
-            // We'll start with silence, and then add the output from the active voices
-            output[0][block_start..block_end].fill(0.0);
-            output[1][block_start..block_end].fill(0.0);
+            // Notes are stopped here when their staggered pedal release is due, and when they're
+            // held for longer than the maximum note length in case a controller dropped their
+            // NoteOffs
+            let mut active_idx = 0;
+            while let Some(&index) = self.active_voices.get(active_idx) {
+                let voice = &self.voices[index];
+                let too_long = max_note_samples > 0 && voice.age >= max_note_samples;
+                if too_long || voice.release_at.is_some_and(|age| voice.age >= age) {
+                    let (channel, note) = (voice.channel, voice.note);
+                    self.stop_voices(context, block_start as u32, channel, note);
+                } else {
+                    active_idx += 1;
+                }
+            }
+
+            // We'll start with silence, and then add the output from the active voices. The voices
+            // are summed in double precision so the rounding errors don't add up with many voices
+            // playing, with the main output first followed by the aux outputs.
+            let mut voice_sum = [[[0.0f64; MAX_BLOCK_SIZE]; 2]; NUM_AUX_OUTPUTS + 1];
 
             let default_gain = self.params.gain.value();
 
-            for voice in self.voices.iter_mut() {
-                if !voice.active {
+            let block_len = block_end - block_start;
+            let mut channel_expression = [[1.0; MAX_BLOCK_SIZE]; NUM_CHANNELS];
+            for (values, expression) in channel_expression.iter_mut().zip(&self.expression) {
+                expression.next_block(values, block_len);
+            }
+            let mut master_expression = [1.0; MAX_BLOCK_SIZE];
+            self.master_expression
+                .next_block(&mut master_expression, block_len);
+
+            let num_active_voices = self.active_voices.len();
+            for voice_idx in 0..num_active_voices + self.fading_voices.len() {
+                let voice = match self.active_voices.get(voice_idx) {
+                    Some(&index) => &mut self.voices[index],
+                    None => &mut self.fading_voices[voice_idx - num_active_voices],
+                };
+                voice.age = voice.age.saturating_add((block_end - block_start) as u32);
+                if reference_tone || metronome {
                     continue;
                 }
 
-                for sample_idx in block_start..block_end {
-                    let velocity_multiplier = db_to_gain(map_value_f32(
-                        voice.velocity,
-                        0.0,
-                        1.0,
-                        -velocity_range,
-                        0.0,
-                    ));
-
-                    let amp = velocity_multiplier * voice.gain.unwrap_or(default_gain);
-
-                    let sample = if sine_wave {
-                        (voice.phase * TAU).sin()
-                    } else {
-                        (voice.phase * 2.0).round() - 1.0
-                    } * amp;
+                let octaves_from_center = (voice.note as f32 - KEY_TRACKING_CENTER as f32) / 12.0;
+                let key_level_multiplier = db_to_gain(key_to_level * octaves_from_center);
 
-                    voice.phase += voice.frequency / sample_rate;
-                    if voice.phase >= 1.0 {
-                        voice.phase -= 1.0;
+                let channel_params = &self.params.channels[voice.channel as usize];
+                let channel_gain = db_to_gain(channel_params.level.value());
+                let channel_frequency_multiplier = cents_to_ratio(
+                    (channel_params.transpose.value() as f32
+                        + self.pitch_bend[voice.channel as usize] * pitch_bend_range)
+                        * 100.0,
+                );
+                let pan = (voice.pan
+                    + channel_params.pan.value()
+                    + key_to_pan * octaves_from_center
+                    + voice.random_pan * random_pan_amount)
+                    .clamp(-1.0, 1.0);
+                let expression = match expression_mode {
+                    ExpressionMode::Channel => &channel_expression[voice.channel as usize],
+                    ExpressionMode::Master => &master_expression,
+                };
+                // The constant power pan law keeps the stereo power constant, but the mono sum
+                // drops by up to 3 dB towards the sides. This undoes that drop.
+                let pan_compensation = if mono_pan_compensation {
+                    1.0 / (pan * 45.0).to_radians().cos()
+                } else {
+                    1.0
+                };
+                // Voices on channels routed to an output the host didn't give us end up on the
+                // main output instead
+                let [sum_left, sum_right] =
+                    match (channel_params.output.value() as usize).checked_sub(1) {
+                        Some(aux_idx)
+                            if aux_outputs
+                                .get(aux_idx)
+                                .is_some_and(|aux_output| aux_output.len() >= 2) =>
+                        {
+                            &mut voice_sum[aux_idx + 1]
+                        }
+                        _ => &mut voice_sum[0],
+                    };
+                let saw_mix = voice.note_on_velocity * velocity_to_saw;
+                let comb_delay = sample_rate
+                    / (voice.frequency
+                        * frequency_multiplier
+                        * channel_frequency_multiplier
+                        * comb_ratio);
+                let loudness_multiplier = if equal_loudness {
+                    equal_loudness_gain(
+                        voice.frequency * frequency_multiplier * channel_frequency_multiplier,
+                    )
+                } else {
+                    1.0
+                };
+
+                if voice.sine_wave != sine_wave {
+                    voice.previous_sine_wave = voice.sine_wave;
+                    voice.sine_wave = sine_wave;
+                    voice.waveform_crossfade = 1.0;
+                }
+
+                for sample_idx in block_start..block_end {
+                    let velocity_multiplier = velocity_gain(
+                        velocity_position(voice.velocity, velocity_interpretation, velocity_range),
+                        velocity_min_db,
+                        velocity_max_db,
+                        velocity_floor,
+                    );
+
+                    let fade_out_gain = match &mut voice.fade_out {
+                        Some(fade_out) => {
+                            let gain = *fade_out;
+                            *fade_out = (gain - declick_step).max(0.0);
+                            gain
+                        }
+                        None => 1.0,
+                    };
+
+                    let amp = velocity_multiplier
+                        * key_level_multiplier
+                        * loudness_multiplier
+                        * pan_compensation
+                        * expression[sample_idx - block_start]
+                        * channel_gain
+                        * voice.soft_pedal_gain
+                        * fade_out_gain
+                        * voice
+                            .gain
+                            .map(|gain| poly_volume_gain(gain, poly_volume_mode))
+                            .unwrap_or(default_gain);
+
+                    let mut left_sample = voice.oscillator(voice.phase);
+                    let mut right_sample = if voice.right_phase == voice.phase {
+                        left_sample
+                    } else {
+                        voice.oscillator(voice.right_phase)
+                    };
+                    if saw_mix > 0.0 {
+                        left_sample += (saw(voice.phase) - left_sample) * saw_mix;
+                        right_sample += (saw(voice.right_phase) - right_sample) * saw_mix;
+                    }
+                    if noise_level > 0.0 {
+                        let noise = voice.noise();
+                        left_sample += (noise - left_sample) * noise_level;
+                        right_sample += (noise - right_sample) * noise_level;
+                    }
+                    if voice_drive > 0.0 {
+                        left_sample = drive(left_sample, voice_drive_gain, voice_drive_mix);
+                        right_sample = drive(right_sample, voice_drive_gain, voice_drive_mix);
+                    }
+                    if comb_feedback != 0.0
+                        && let Some(comb) = voice.comb
+                    {
+                        [left_sample, right_sample] = self.comb_filters.get_mut(comb).process(
+                            [left_sample, right_sample],
+                            comb_delay,
+                            comb_feedback,
+                        );
+                    }
+                    left_sample *= amp;
+                    right_sample *= amp;
+                    voice.waveform_crossfade = (voice.waveform_crossfade - declick_step).max(0.0);
+
+                    let drift_multiplier = if voice.onset_drift != 0.0 {
+                        let multiplier = cents_to_ratio(voice.onset_drift);
+                        voice.onset_drift *= onset_drift_decay;
+                        if voice.onset_drift.abs() < 0.001 {
+                            voice.onset_drift = 0.0;
+                        }
+
+                        multiplier
+                    } else {
+                        1.0
+                    };
+                    let frequency = clamp_frequency(
+                        voice.frequency
+                            * frequency_multiplier
+                            * channel_frequency_multiplier
+                            * drift_multiplier,
+                        sample_rate,
+                    );
+                    let phase_delta = frequency / sample_rate;
+                    voice.phase = advance_phase(voice.phase, phase_delta);
+                    voice.right_phase = advance_phase(voice.right_phase, phase_delta);
+
+                    let (left, _) = constant_power_pan(left_sample, pan);
+                    let (_, right) = constant_power_pan(right_sample, pan);
+
+                    sum_left[sample_idx - block_start] += left as f64;
+                    sum_right[sample_idx - block_start] += right as f64;
+                }
+            }
+
+            write_voice_sum(output, block_start..block_end, &voice_sum[0]);
+            for (aux_output, voice_sum) in aux_outputs.iter_mut().zip(&voice_sum[1..]) {
+                write_voice_sum(aux_output, block_start..block_end, voice_sum);
+            }
+
+            // The reference tone replaces the voices with a steady sine at the note gain, which is
+            // useful for checking the tuning against other gear. MIDI is still tracked so no notes
+            // get stuck while it's enabled.
+            if reference_tone {
+                let (left, right) = stereo_channels(output);
+                for (left, right) in left[block_start..block_end]
+                    .iter_mut()
+                    .zip(&mut right[block_start..block_end])
+                {
+                    let sample = (self.reference_phase * TAU).sin() * default_gain;
+                    *left = sample;
+                    *right = sample;
+
+                    self.reference_phase =
+                        advance_phase(self.reference_phase, reference_frequency / sample_rate);
+                }
+            }
+
+            // The metronome also replaces the voices, so the clicks can be checked against the
+            // host's own metronome without anything else playing
+            #[cfg(debug_assertions)]
+            if metronome {
+                let click_samples = (METRONOME_CLICK_MS / 1000.0 * sample_rate) as u32;
+                let (left, right) = stereo_channels(output);
+                for (sample_idx, (left, right)) in left[block_start..block_end]
+                    .iter_mut()
+                    .zip(&mut right[block_start..block_end])
+                    .enumerate()
+                {
+                    if let Some((start_beats, beats_per_sample)) = metronome_beats {
+                        let beat =
+                            start_beats + (block_start + sample_idx) as f64 * beats_per_sample;
+                        if beat.floor() != (beat - beats_per_sample).floor() {
+                            self.metronome_click = Some(0);
+                        }
                     }
 
-                    let (left, right) = constant_power_pan(sample, voice.pan * 2.0);
+                    let Some(click_age) = self.metronome_click else {
+                        continue;
+                    };
+                    let time = click_age as f32 / sample_rate;
+                    let envelope = 1.0 - click_age as f32 / click_samples as f32;
+                    let sample = (time * METRONOME_FREQUENCY * TAU).sin() * envelope * default_gain;
+                    *left += sample;
+                    *right += sample;
+
+                    self.metronome_click = (click_age + 1 < click_samples).then_some(click_age + 1);
+                }
+            }
+
+            self.fading_voices.retain(|voice| {
+                let fading = voice.fade_out.is_some_and(|gain| gain > 0.0);
+                if !fading && let Some(comb) = voice.comb {
+                    self.comb_filters.release(comb);
+                }
+
+                fading
+            });
 
-                    output[0][sample_idx] += left;
-                    output[1][sample_idx] += right;
+            self.phaser
+                .process(output, block_start..block_end, &self.params, sample_rate);
+
+            self.effects
+                .process(output, block_start..block_end, &self.params);
+
+            self.eq
+                .process(output, block_start..block_end, eq_bands, sample_rate);
+
+            self.haas
+                .process(output, block_start..block_end, haas_delay);
+
+            if mono_safety {
+                self.mono_safety.process(
+                    output,
+                    block_start..block_end,
+                    mono_safety_threshold,
+                    sample_rate,
+                );
+            } else {
+                self.mono_safety.reset();
+            }
+
+            // The aux outputs skip the master effects, but they're limited the same way as the main
+            // output
+            let [main_limiter, aux_limiters @ ..] = &mut self.true_peak_limiters;
+            let limit = |limiter: &mut TruePeakLimiter, channels: &mut [&mut [f32]]| {
+                if true_peak_limit {
+                    limiter.process(
+                        channels,
+                        block_start..block_end,
+                        true_peak_ceiling,
+                        limiter_attack,
+                        limiter_release,
+                        sample_rate,
+                    );
+                } else {
+                    limiter.reset();
                 }
+            };
+            limit(main_limiter, output);
+            for (limiter, aux_output) in aux_limiters.iter_mut().zip(aux_outputs.iter_mut()) {
+                limit(limiter, aux_output);
             }
 
             // And then just keep processing blocks until we've run out of buffer to fill
@@ -296,44 +1753,260 @@ impl Plugin for PolyModSynth {
             block_end = (block_start + MAX_BLOCK_SIZE).min(num_samples);
         }
 
-        ProcessStatus::Normal
+        let soft_start_gain = self.soft_start_gain;
+        self.soft_start_gain =
+            finish_output_bus(output, soft_start_gain, declick_step, &self.params);
+        for aux_output in aux_outputs.iter_mut() {
+            finish_output_bus(aux_output, soft_start_gain, declick_step, &self.params);
+        }
+
+        self.correlation
+            .store(stereo_correlation(output[0], output[1]), Ordering::Relaxed);
+        self.scope
+            .process(output, self.params.scope_trigger_level.value());
+        self.diagnostics
+            .process(num_samples, self.active_voices.len(), sample_rate);
+
+        // NaNs and infinities propagate through everything downstream of us, so catch them here
+        debug_assert!(
+            output
+                .iter()
+                .all(|channel| channel.iter().all(|sample| sample.is_finite())),
+            "non-finite sample in output"
+        );
+    }
+
+    /// A handle to the stereo correlation of the last processed buffer, for the editor to display.
+    pub fn correlation(&self) -> Arc<AtomicF32> {
+        self.correlation.clone()
+    }
+
+    /// A handle to the oscilloscope's latest captured frame, for the editor to display.
+    pub fn scope_frame(&self) -> Arc<ScopeFrame> {
+        self.scope.frame()
+    }
+
+    /// A handle to the voice statistics, for the editor to display.
+    pub fn diagnostics(&self) -> Arc<Diagnostics> {
+        self.diagnostics.diagnostics()
+    }
+
+    /// Replace the velocity curve with `curve`'s `(input, output)` breakpoints. The breakpoints are
+    /// sorted by input and clamped to `[0, 1]`, and the curve is left unchanged if any of them
+    /// isn't a number.
+    pub fn set_velocity_curve(&self, curve: &[(f32, f32)]) -> Result<(), TableError> {
+        if curve
+            .iter()
+            .any(|(input, output)| input.is_nan() || output.is_nan())
+        {
+            return Err(TableError::NotANumber);
+        }
+
+        let mut curve: Vec<_> = curve
+            .iter()
+            .map(|(input, output)| (input.clamp(0.0, 1.0), output.clamp(0.0, 1.0)))
+            .collect();
+        curve.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        *self
+            .params
+            .velocity_curve
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = curve;
+
+        Ok(())
+    }
+
+    /// Replace the tuning table with `cents`, the offset in cents from 12-TET for every MIDI note.
+    /// The table is left unchanged if it doesn't have exactly 128 entries or if any of them isn't
+    /// a finite number.
+    pub fn set_tuning_table(&self, cents: &[f32]) -> Result<(), TableError> {
+        if cents.len() != NUM_NOTES {
+            return Err(TableError::WrongLength(cents.len()));
+        }
+        if !cents.iter().all(|cents| cents.is_finite()) {
+            return Err(TableError::NotANumber);
+        }
+
+        *self
+            .params
+            .tuning_table
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = cents.to_vec();
+
+        Ok(())
+    }
+
+    /// Map a velocity through the velocity curve. If the curve is being written to at the same
+    /// time, the velocity is passed through unchanged instead of blocking the audio thread.
+    fn map_velocity(&self, velocity: f32) -> f32 {
+        match self.params.velocity_curve.try_read() {
+            Ok(curve) => apply_velocity_curve(&curve, velocity).clamp(0.0, 1.0),
+            Err(_) => velocity,
+        }
+    }
+
+    /// The phase, from 0 to 1, that new notes with the given waveform start at.
+    fn start_phase(&self, sine_wave: bool) -> f32 {
+        if self.params.start_phase_override.value() {
+            (self.params.start_phase.value() / 360.0).fract()
+        } else {
+            default_start_phase(sine_wave)
+        }
+    }
+
+    /// The tuning table's offset for `note` in cents. Like with the velocity curve, the table is
+    /// skipped instead of blocking the audio thread if it's being written to.
+    fn tuning_offset(&self, note: u8) -> f32 {
+        match self.params.tuning_table.try_read() {
+            Ok(table) => table
+                .get(note as usize)
+                .copied()
+                .filter(|cents| cents.is_finite())
+                .unwrap_or(0.0),
+            Err(_) => 0.0,
+        }
+    }
+
+    /// Returns the voice for a (channel, note) pair, or `None` if the event is out of range.
+    fn voice_mut(&mut self, channel: u8, note: u8) -> Option<&mut Voice> {
+        voice_index(channel, note).map(|index| &mut self.voices[index])
     }
-}
 
-impl PolyModSynth {
     fn start_voice(
         &mut self,
-        _context: &mut impl ProcessContext<Self>,
-        _sample_offset: u32,
+        context: &mut impl ProcessHost,
+        sample_offset: u32,
         channel: u8,
         note: u8,
         voice_id: Option<i32>,
-    ) -> &mut Voice {
-        let voice = &mut self.voices[(channel as usize * 128) + note as usize];
+    ) -> Option<&mut Voice> {
+        // Events for voices that don't exist are ignored before they can use up a random number
+        let index = voice_index(channel, note)?;
+        let onset_drift =
+            next_random(&mut self.onset_drift_state) * self.params.onset_drift.value();
+        let random_pan_seed = self.params.random_pan_seed.value() as u32;
+        let stereo_phase = self.params.stereo_phase.value() / 360.0;
+        let sine_wave = self.params.sine_wave.value();
+        let start_phase = self.start_phase(sine_wave);
+        let frequency = util::midi_note_to_freq(note) * cents_to_ratio(self.tuning_offset(note));
+        let soft_pedal_gain = if self.soft_pedal[channel as usize] {
+            db_to_gain(-self.params.soft_pedal_attenuation.value())
+        } else {
+            1.0
+        };
+        let voice = &mut self.voices[index];
 
         debug_assert_eq!(voice.channel, channel);
         debug_assert_eq!(voice.note, note);
 
+        self.diagnostics.note_started(voice.active);
+
+        // If the slot is still playing, the old sound is moved to the fading voices so the new
+        // note can start cleanly without cutting it off
+        if voice.active {
+            // The fading copy keeps the old comb filter, and the new note gets a fresh one
+            if self.fading_voices.len() < MAX_FADING_VOICES {
+                self.fading_voices.push(Voice {
+                    fade_out: Some(1.0),
+                    ..voice.clone()
+                });
+            } else if let Some(comb) = voice.comb {
+                self.comb_filters.release(comb);
+            }
+            voice.comb = None;
+
+            // The host still needs to hear about stolen voices without an ID, since it tracks
+            // those by their channel and note. It's only skipped if the new note reuses the ID.
+            if voice.voice_id.is_none() || voice.voice_id != voice_id {
+                context.send_event(NoteEvent::VoiceTerminated {
+                    timing: sample_offset,
+                    voice_id: voice.voice_id,
+                    channel,
+                    note,
+                });
+            }
+        } else {
+            self.active_voices.push(index);
+        }
+
         voice.voice_id = voice_id;
         voice.active = true;
+        voice.frequency = frequency;
         voice.velocity = 0.0;
         voice.pan = 0.0;
         voice.gain = None;
+        voice.release_pending = false;
+        voice.soft_pedal_gain = soft_pedal_gain;
+        voice.phase = start_phase;
+        voice.right_phase = (start_phase + stereo_phase).fract();
+        voice.sine_wave = sine_wave;
+        voice.waveform_crossfade = 0.0;
+        voice.age = 0;
+        voice.noise_state = noise_seed(index);
+        voice.comb = self.comb_filters.acquire();
+        voice.onset_drift = onset_drift;
+        voice.random_pan = random_pan(random_pan_seed, index);
+        voice.release_at = None;
 
-        voice
+        Some(voice)
     }
+
+    /// Handle a NoteOn for a note that's already playing without restarting it. This is used for
+    /// controllers that keep resending NoteOns for held notes, which would otherwise retrigger the
+    /// note every time.
+    fn continue_voice(
+        &mut self,
+        context: &mut impl ProcessHost,
+        sample_offset: u32,
+        channel: u8,
+        note: u8,
+        voice_id: Option<i32>,
+    ) -> Option<&mut Voice> {
+        let voice = self.voice_mut(channel, note)?;
+
+        // The host considers the new voice ID to be playing from now on
+        if voice.voice_id.is_some() && voice.voice_id != voice_id {
+            context.send_event(NoteEvent::VoiceTerminated {
+                timing: sample_offset,
+                voice_id: voice.voice_id,
+                channel,
+                note,
+            });
+        }
+
+        voice.voice_id = voice_id;
+        voice.release_pending = false;
+        voice.release_at = None;
+        voice.age = 0;
+
+        Some(voice)
+    }
+
     fn stop_voices(
         &mut self,
-        context: &mut impl ProcessContext<Self>,
+        context: &mut impl ProcessHost,
         sample_offset: u32,
         channel: u8,
         note: u8,
     ) {
-        let voice = &mut self.voices[(channel as usize * 128) + note as usize];
+        let Some(index) = voice_index(channel, note) else {
+            return;
+        };
+        let voice = &mut self.voices[index];
 
         debug_assert_eq!(voice.channel, channel);
         debug_assert_eq!(voice.note, note);
 
+        // The host has already been told about voices that aren't playing, and telling it again
+        // could terminate a voice it has since reused the ID for
+        if !voice.active {
+            return;
+        }
+
+        if let Some(position) = self.active_voices.iter().position(|&i| i == index) {
+            self.active_voices.swap_remove(position);
+        }
+
         context.send_event(NoteEvent::VoiceTerminated {
             timing: sample_offset,
             voice_id: voice.voice_id,
@@ -342,25 +2015,478 @@ impl PolyModSynth {
         });
 
         voice.active = false;
+        voice.sostenuto_held = false;
+        voice.release_pending = false;
+        voice.release_at = None;
+        if let Some(comb) = voice.comb.take() {
+            self.comb_filters.release(comb);
+        }
         voice.phase = 0.0;
+        voice.right_phase = 0.0;
+    }
+
+    /// Handle a NoteOff. Notes that are latched by the sostenuto pedal keep playing until the pedal
+    /// is lifted, and in drone mode NoteOffs are ignored entirely. Those notes can still be stopped
+    /// with a choke event or with the channel mode messages.
+    fn release_voice(
+        &mut self,
+        context: &mut impl ProcessHost,
+        sample_offset: u32,
+        channel: u8,
+        note: u8,
+    ) {
+        if self.params.drone_mode.value() {
+            return;
+        }
+
+        if let Some(voice) = self.voice_mut(channel, note)
+            && voice.sostenuto_held
+        {
+            voice.release_pending = true;
+            return;
+        }
+
+        self.stop_voices(context, sample_offset, channel, note);
+    }
+
+    /// Handle Reset All Controllers (CC121) by releasing all pedals on the channel and resetting
+    /// its pitch bend and expression.
+    fn reset_controllers(
+        &mut self,
+        context: &mut impl ProcessHost,
+        sample_offset: u32,
+        channel: u8,
+    ) {
+        self.set_sostenuto(context, sample_offset, channel, false);
+        if let Some(soft_pedal) = self.soft_pedal.get_mut(channel as usize) {
+            *soft_pedal = false;
+        }
+        if let Some(pitch_bend) = self.pitch_bend.get_mut(channel as usize) {
+            *pitch_bend = 0.0;
+        }
+        if let Some(expression) = self.expression.get(channel as usize) {
+            expression.set_target(context.sample_rate(), 1.0);
+        }
+    }
+
+    /// Handle the sostenuto pedal (CC66). Pressing it latches the notes that are currently held on
+    /// the channel, and lifting it stops the latched notes that have been released in the meantime.
+    /// Those notes are spread out over the pedal release stagger time, if it's set.
+    fn set_sostenuto(
+        &mut self,
+        context: &mut impl ProcessHost,
+        sample_offset: u32,
+        channel: u8,
+        pressed: bool,
+    ) {
+        let Some(pedal) = self.sostenuto.get_mut(channel as usize) else {
+            return;
+        };
+        if *pedal == pressed {
+            return;
+        }
+        *pedal = pressed;
+
+        let stagger_samples =
+            self.params.pedal_release_stagger.value() / 1000.0 * context.sample_rate();
+        let num_released = match voice_index(channel, 0) {
+            Some(first_index) if !pressed => self.voices[first_index..first_index + NUM_NOTES]
+                .iter()
+                .filter(|voice| voice.sostenuto_held && voice.release_pending)
+                .count(),
+            _ => 0,
+        };
+
+        let mut num_stopped = 0;
+        for note in 0..NUM_NOTES as u8 {
+            let Some(voice) = self.voice_mut(channel, note) else {
+                continue;
+            };
+
+            if pressed {
+                voice.sostenuto_held = voice.active;
+            } else if voice.sostenuto_held {
+                voice.sostenuto_held = false;
+                if voice.release_pending {
+                    let release_delay = if num_released > 1 {
+                        stagger_samples * num_stopped as f32 / (num_released - 1) as f32
+                    } else {
+                        0.0
+                    };
+                    num_stopped += 1;
+
+                    if release_delay >= 1.0 {
+                        voice.release_at = Some(voice.age.saturating_add(release_delay as u32));
+                    } else {
+                        self.stop_voices(context, sample_offset, channel, note);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Voice {
+    /// Render the voice's waveform at `phase`, crossfading from the previous waveform if the
+    /// waveform was changed recently.
+    fn oscillator(&self, phase: f32) -> f32 {
+        let sample = oscillator(phase, self.sine_wave);
+        if self.waveform_crossfade > 0.0 {
+            sample + (oscillator(phase, self.previous_sine_wave) - sample) * self.waveform_crossfade
+        } else {
+            sample
+        }
+    }
+
+    /// Generate the next white noise sample in `[-1, 1]`.
+    fn noise(&mut self) -> f32 {
+        next_random(&mut self.noise_state)
+    }
+}
+
+fn oscillator(phase: f32, sine_wave: bool) -> f32 {
+    if sine_wave {
+        (phase * TAU).sin()
+    } else {
+        (phase * 2.0).round() - 1.0
+    }
+}
+
+/// A rising sawtooth wave that crosses zero at the start of the cycle, so it lines up with the sine
+/// wave.
+fn saw(phase: f32) -> f32 {
+    (phase + 0.5).fract() * 2.0 - 1.0
+}
+
+/// A tanh waveshaper with `gain` applied before the saturation, blended with the dry `sample` by
+/// `mix`. The output is scaled so a full scale input still peaks at 1.
+fn drive(sample: f32, gain: f32, mix: f32) -> f32 {
+    let shaped = (sample * gain).tanh() / gain.tanh();
+
+    sample + (shaped - sample) * mix
+}
+
+/// The phase each waveform starts at unless it's overridden. These are the points where the
+/// waveforms are at zero, so notes don't start with a click.
+fn default_start_phase(sine_wave: bool) -> f32 {
+    if sine_wave {
+        0.0
+    } else {
+        // The stepped wave is at -1 until a quarter of the way through its cycle
+        0.25
+    }
+}
+
+/// Keeps the frequency between 0 Hz and Nyquist so stacked pitch modulation can never produce a
+/// negative or aliasing phase increment. Frequencies that aren't a number are silenced.
+fn clamp_frequency(frequency: f32, sample_rate: f32) -> f32 {
+    if frequency.is_nan() {
+        0.0
+    } else {
+        frequency.clamp(0.0, sample_rate / 2.0)
+    }
+}
+
+fn advance_phase(phase: f32, delta: f32) -> f32 {
+    let phase = phase + delta;
+    if phase >= 1.0 { phase - 1.0 } else { phase }
+}
+
+/// The Pearson correlation between the left and right channels. Silence is reported as fully
+/// correlated since it can't cancel out when summed to mono.
+fn stereo_correlation(left: &[f32], right: &[f32]) -> f32 {
+    let (mut lr, mut ll, mut rr) = (0.0, 0.0, 0.0);
+    for (&l, &r) in left.iter().zip(right) {
+        lr += l * r;
+        ll += l * l;
+        rr += r * r;
+    }
+
+    let power = (ll * rr).sqrt();
+    if power > 0.0 {
+        (lr / power).clamp(-1.0, 1.0)
+    } else {
+        1.0
+    }
+}
+
+/// Advance the xorshift PRNG `state` and return a random value in `[-1, 1]`.
+fn next_random(state: &mut u32) -> f32 {
+    *state ^= *state << 13;
+    *state ^= *state >> 17;
+    *state ^= *state << 5;
+
+    *state as f32 / u32::MAX as f32 * 2.0 - 1.0
+}
+
+/// The initial state of the PRNG for the onset pitch drift.
+fn onset_drift_seed() -> u32 {
+    // This is the seed right after the last voice's, so it doesn't match any voice's noise seed
+    noise_seed(NUM_CHANNELS * NUM_NOTES)
+}
+
+/// The random pan position from -1 to 1 for the voice at `index`. This only depends on `seed` and
+/// the voice, so the same seed always gives every note the same position.
+fn random_pan(seed: u32, index: usize) -> f32 {
+    // The seed is spread out over the bits before mixing it with the voice's noise seed, and the
+    // first few outputs are skipped since xorshift needs a couple of rounds to mix similar states
+    let mut state = noise_seed(index) ^ seed.wrapping_mul(0x85EB_CA6B);
+    if state == 0 {
+        state = 1;
+    }
+    for _ in 0..3 {
+        next_random(&mut state);
+    }
+
+    next_random(&mut state)
+}
+
+/// The initial noise generator state for the voice at `index`. Every voice gets a different
+/// sequence, and the state is never zero since xorshift would get stuck there.
+fn noise_seed(index: usize) -> u32 {
+    // Multiplying by an odd constant is a bijection, so this only maps zero to zero
+    (index as u32 + 1).wrapping_mul(0x9E37_79B9)
+}
+
+/// A smoother for the expression controller, starting out at full expression.
+fn expression_smoother() -> Smoother<f32> {
+    let smoother = Smoother::new(SmoothingStyle::Linear(EXPRESSION_SMOOTHING_MS));
+    smoother.reset(1.0);
+
+    smoother
+}
+
+/// Write the double precision voice sum for a block to `channels[..][range]`.
+fn write_voice_sum(
+    channels: &mut [&mut [f32]],
+    range: std::ops::Range<usize>,
+    voice_sum: &[[f64; MAX_BLOCK_SIZE]; 2],
+) {
+    for (channel, voice_sum) in channels.iter_mut().zip(voice_sum) {
+        for (sample, &sum) in channel[range.clone()].iter_mut().zip(voice_sum) {
+            *sample = sum as f32;
+        }
+    }
+}
+
+/// The last stages of the chain, which the main output and every aux output go through. This fades
+/// `channels` in from `soft_start_gain` over the declick time, keeps a boosted gain from pushing it
+/// past full scale, and swaps and inverts its channels. Returns the soft start gain at the end of
+/// the buffer.
+fn finish_output_bus(
+    channels: &mut [&mut [f32]],
+    soft_start_gain: f32,
+    declick_step: f32,
+    params: &PolyModSynthParams,
+) -> f32 {
+    let mut end_gain = soft_start_gain;
+    if soft_start_gain < 1.0 {
+        for channel in channels.iter_mut() {
+            end_gain = soft_start_gain;
+            for sample in channel.iter_mut() {
+                *sample *= end_gain;
+                end_gain = (end_gain + declick_step).min(1.0);
+            }
+        }
+    }
+
+    // Boosting the gain above 0 dB could otherwise push the output past full scale. Only the
+    // samples above full scale are touched, so quieter material is boosted by the full amount.
+    if params.gain.value() > 1.0 {
+        for channel in channels.iter_mut() {
+            for sample in channel.iter_mut() {
+                *sample = sample.clamp(-1.0, 1.0);
+            }
+        }
+    }
+
+    if params.swap_channels.value()
+        && let [left, right, ..] = channels
+    {
+        left.swap_with_slice(right);
+    }
+    for (channel, invert) in channels
+        .iter_mut()
+        .zip([&params.invert_left, &params.invert_right])
+    {
+        if invert.value() {
+            for sample in channel.iter_mut() {
+                *sample = -*sample;
+            }
+        }
+    }
+
+    end_gain
+}
+
+/// Store `sample_rate` in `allocated`, returning whether it changed. The delay lines are allocated
+/// from the plugin's `initialize()`, which the host can call again without changing the sample
+/// rate, so they use this to only reallocate their buffers when they're actually the wrong size.
+fn sample_rate_changed(allocated: &mut f32, sample_rate: f32) -> bool {
+    if *allocated == sample_rate {
+        return false;
+    }
+    *allocated = sample_rate;
+
+    true
+}
+
+/// Split the first two channels of a buffer into separate left and right slices.
+fn stereo_channels<'a>(channels: &'a mut [&mut [f32]]) -> (&'a mut [f32], &'a mut [f32]) {
+    let (left, right) = channels.split_at_mut(1);
+
+    (&mut *left[0], &mut *right[0])
+}
+
+fn voice_index(channel: u8, note: u8) -> Option<usize> {
+    let (channel, note) = (channel as usize, note as usize);
+
+    (channel < NUM_CHANNELS && note < NUM_NOTES).then_some(channel * NUM_NOTES + note)
+}
+
+/// Where `velocity` falls within a velocity range of `velocity_range` decibels, from 0 at the bottom
+/// of the range to 1 at the top.
+fn velocity_position(
+    velocity: f32,
+    interpretation: VelocityInterpretation,
+    velocity_range: f32,
+) -> f32 {
+    match interpretation {
+        VelocityInterpretation::Perceptual => velocity,
+        VelocityInterpretation::Linear if velocity_range > 0.0 => {
+            (util::gain_to_db(velocity) / velocity_range + 1.0).clamp(0.0, 1.0)
+        }
+        VelocityInterpretation::Linear => 1.0,
+        VelocityInterpretation::Dynamics => {
+            let velocity = velocity * 127.0;
+            let steps = DYNAMICS_VELOCITIES.len() - 1;
+            let step = DYNAMICS_VELOCITIES[1..]
+                .iter()
+                .position(|&marking| velocity < marking as f32)
+                .unwrap_or(steps - 1);
+            let (lower, upper) = (
+                DYNAMICS_VELOCITIES[step] as f32,
+                DYNAMICS_VELOCITIES[step + 1] as f32,
+            );
+
+            (step as f32 + ((velocity - lower) / (upper - lower)).clamp(0.0, 1.0)) / steps as f32
+        }
+    }
+}
+
+/// Shows a velocity range in decibels along with the span of dynamic markings it covers, centered
+/// on the middle of the scale. A 30 dB range is shown as `30.0 dB (pp-ff)`.
+fn velocity_range_to_string(velocity_range: f32) -> String {
+    let steps =
+        ((velocity_range / DYNAMICS_STEP_DB).round() as usize).min(DYNAMICS_MARKINGS.len() - 1);
+    if steps == 0 {
+        return format!("{velocity_range:.1} dB");
+    }
+
+    let quietest = (DYNAMICS_MARKINGS.len() - 1 - steps) / 2;
+    format!(
+        "{velocity_range:.1} dB ({}-{})",
+        DYNAMICS_MARKINGS[quietest],
+        DYNAMICS_MARKINGS[quietest + steps]
+    )
+}
+
+/// Parses either a velocity range in decibels, or a span of dynamic markings like `pp-ff` which is
+/// mapped to [`DYNAMICS_STEP_DB`] decibels per marking.
+fn string_to_velocity_range(string: &str) -> Option<f32> {
+    let string = string.trim();
+    let decibels = string.split('(').next().unwrap_or_default();
+    if let Ok(velocity_range) = decibels.trim().trim_end_matches("dB").trim().parse() {
+        return Some(velocity_range);
+    }
+
+    let marking_index = |marking: &str| {
+        DYNAMICS_MARKINGS
+            .iter()
+            .position(|&name| name.eq_ignore_ascii_case(marking.trim()))
+    };
+    let (from, to) = string
+        .split_once("..")
+        .or_else(|| string.split_once(['-', '\u{2013}']))?;
+    let steps = marking_index(from)?.abs_diff(marking_index(to)?);
+
+    Some(steps as f32 * DYNAMICS_STEP_DB)
+}
+
+/// The gain for a note at `position` within a velocity range going from `min_db` to `max_db`,
+/// never going below the gain `floor`.
+fn velocity_gain(position: f32, min_db: f32, max_db: f32, floor: f32) -> f32 {
+    db_to_gain(map_value_f32(position, 0.0, 1.0, min_db, max_db)).max(floor)
+}
+
+/// Map `velocity` through the breakpoints in `curve`, interpolating linearly between them.
+/// Velocities outside of the curve's range use the nearest breakpoint's output.
+fn apply_velocity_curve(curve: &[(f32, f32)], velocity: f32) -> f32 {
+    let (Some(&(first_input, first_output)), Some(&(_, last_output))) =
+        (curve.first(), curve.last())
+    else {
+        return velocity;
+    };
+    if velocity <= first_input {
+        return first_output;
     }
+
+    for breakpoints in curve.windows(2) {
+        let [(input_min, output_min), (input_max, output_max)] = [breakpoints[0], breakpoints[1]];
+        if velocity <= input_max {
+            return if input_max > input_min {
+                map_value_f32(velocity, input_min, input_max, output_min, output_max)
+            } else {
+                output_max
+            };
+        }
+    }
+
+    last_output
 }
 
 fn map_value_f32(x: f32, min: f32, max: f32, target_min: f32, target_max: f32) -> f32 {
     (x - min) / (max - min) * (target_max - target_min) + target_min
 }
 
+fn poly_volume_gain(gain: f32, mode: PolyVolumeMode) -> f32 {
+    match mode {
+        PolyVolumeMode::Linear => gain,
+        // 40 * log10(x) dB is the same as squaring, so full scale stays at unity in both modes
+        PolyVolumeMode::Decibels => gain * gain,
+    }
+}
+
+/// The gain that compensates for the ear's sensitivity at `frequency`, using the inverse of the
+/// A-weighting curve. Notes around 1 kHz stay at unity gain.
+fn equal_loudness_gain(frequency: f32) -> f32 {
+    db_to_gain((-a_weighting_db(frequency)).min(MAX_EQUAL_LOUDNESS_BOOST_DB))
+}
+
+/// The IEC 61672 A-weighting of `frequency` in decibels, normalized to 0 dB at 1 kHz.
+fn a_weighting_db(frequency: f32) -> f32 {
+    let f2 = frequency * frequency;
+    let response = 12194.0f32.powi(2) * f2 * f2
+        / ((f2 + 20.6f32.powi(2))
+            * ((f2 + 107.7f32.powi(2)) * (f2 + 737.9f32.powi(2))).sqrt()
+            * (f2 + 12194.0f32.powi(2)));
+
+    20.0 * response.log10() + 2.0
+}
+
+fn cents_to_ratio(cents: f32) -> f32 {
+    2.0_f32.powf(cents / 1200.0)
+}
+
+/// Pan `value` with a constant power pan law, with `pan` going from -1 (left) to 1 (right). The
+/// law is scaled so a centered signal passes through at unity gain on both channels, which keeps
+/// the transition through the center continuous.
 fn constant_power_pan(value: f32, pan: f32) -> (f32, f32) {
-    if pan == 0.0 {
-        (value, value)
-    } else {
-        let angle = (pan * 45.0).to_radians();
-        let coeff = f32::sqrt(2.0) / 2.0;
-        let cos = f32::cos(angle);
-        let sin = f32::sin(angle);
+    let angle = (pan * 45.0).to_radians();
+    let cos = f32::cos(angle);
+    let sin = f32::sin(angle);
 
-        (coeff * (cos - sin) * value, coeff * (cos + sin) * value)
-    }
+    ((cos - sin) * value, (cos + sin) * value)
 }
 
 impl ClapPlugin for PolyModSynth {
@@ -386,3 +2512,1791 @@ impl Vst3Plugin for PolyModSynth {
 
 nih_export_clap!(PolyModSynth);
 nih_export_vst3!(PolyModSynth);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_host::{
+        TestHost, float_param, int_param, measure_frequency, note_off, note_on, rising_crossings,
+        test_synth, tone_amplitude,
+    };
+
+    /// Render a dense stream of notes, pitch bends, and controller changes on every channel with
+    /// `params`, and assert that every output sample is finite.
+    fn assert_finite_render(params: PolyModSynthParams) {
+        let mut host = TestHost {
+            tempo: Some(240.0),
+            pos_beats: Some(0.0),
+            playing: true,
+            ..TestHost::default()
+        };
+        let mut synth = test_synth(params, &host);
+
+        for buffer_idx in 0..16u32 {
+            for step in 0..32u32 {
+                let timing = step * 16;
+                let channel = ((buffer_idx + step) % NUM_CHANNELS as u32) as u8;
+                let note = ((buffer_idx * 37 + step * 11) % NUM_NOTES as u32) as u8;
+                let extreme = if step % 2 == 0 { 0.0 } else { 1.0 };
+                host.events.extend([
+                    note_on(timing, channel, note, Some((buffer_idx * 32 + step) as i32)),
+                    NoteEvent::PolyVolume {
+                        timing,
+                        voice_id: None,
+                        channel,
+                        note,
+                        gain: extreme * 4.0,
+                    },
+                    NoteEvent::PolyPan {
+                        timing,
+                        voice_id: None,
+                        channel,
+                        note,
+                        pan: extreme * 2.0 - 1.0,
+                    },
+                    NoteEvent::MidiPitchBend {
+                        timing,
+                        channel,
+                        value: extreme,
+                    },
+                    NoteEvent::MidiCC {
+                        timing,
+                        channel,
+                        cc: [EXPRESSION_CONTROLLER_MSB, SUSTENUTO, SOFT_PEDAL][step as usize % 3],
+                        value: extreme,
+                    },
+                    note_off(timing + 8, channel, note.wrapping_sub(11) % NUM_NOTES as u8),
+                ]);
+            }
+
+            let output = host.render(&mut synth, 512);
+            for (channel, samples) in output.iter().enumerate() {
+                if let Some(idx) = samples.iter().position(|sample| !sample.is_finite()) {
+                    panic!(
+                        "non-finite sample {} at {buffer_idx}:{channel}:{idx}",
+                        samples[idx]
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_host_plays_notes_at_their_frequency() {
+        let mut host = TestHost::default();
+        let mut synth = test_synth(PolyModSynthParams::default(), &host);
+        host.events.push_back(note_on(0, 0, 69, Some(1)));
+        let [left, right] = host.render(&mut synth, 48000);
+
+        // A 440 Hz sine crosses zero going up 440 times per second
+        let rising_crossings = left
+            .windows(2)
+            .filter(|samples| samples[0] < 0.0 && samples[1] >= 0.0)
+            .count();
+        assert!((439..=441).contains(&rising_crossings));
+        assert_eq!(left, right);
+
+        host.events.push_back(NoteEvent::NoteOff {
+            timing: 10,
+            voice_id: Some(1),
+            channel: 0,
+            note: 69,
+            velocity: 0.0,
+        });
+        let [left, _] = host.render(&mut synth, 64);
+        assert!(left[10..].iter().all(|&sample| sample == 0.0));
+        assert_eq!(host.sent_events.len(), 1);
+    }
+
+    #[test]
+    fn detune_shifts_the_rendered_frequency() {
+        for detune in [50.0, -50.0] {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    detune: float_param(detune),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events.push_back(note_on(0, 0, 69, None));
+            let [left, _] = host.render(&mut synth, 48000);
+
+            let expected = 440.0 * 2.0f32.powf(detune / 1200.0);
+            let frequency = measure_frequency(&left, host.sample_rate);
+            assert!(
+                (frequency - expected).abs() < 0.05,
+                "{detune} cents rendered at {frequency} Hz"
+            );
+        }
+    }
+
+    #[test]
+    fn poly_volume_gain_keeps_full_scale_at_unity() {
+        for mode in [PolyVolumeMode::Linear, PolyVolumeMode::Decibels] {
+            assert_eq!(poly_volume_gain(1.0, mode), 1.0);
+            assert_eq!(poly_volume_gain(0.0, mode), 0.0);
+        }
+
+        assert_eq!(poly_volume_gain(0.5, PolyVolumeMode::Linear), 0.5);
+        // 40 * log10(0.5) dB is about -12 dB
+        let gain = poly_volume_gain(0.5, PolyVolumeMode::Decibels);
+        assert!((util::gain_to_db(gain) - 40.0 * 0.5f32.log10()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn voice_index_addresses_every_voice() {
+        let synth = PolyModSynth::default();
+        for channel in 0..NUM_CHANNELS as u8 {
+            for note in 0..NUM_NOTES as u8 {
+                let index = voice_index(channel, note).unwrap();
+                assert_eq!(index, channel as usize * 128 + note as usize);
+
+                let voice = &synth.voices[index];
+                assert_eq!((voice.channel, voice.note), (channel, note));
+            }
+        }
+
+        assert_eq!(voice_index(16, 0), None);
+        assert_eq!(voice_index(0, 128), None);
+        assert_eq!(voice_index(u8::MAX, u8::MAX), None);
+    }
+
+    #[test]
+    fn velocity_gain_is_clamped_to_the_floor() {
+        let floor = db_to_gain(-20.0);
+        let gain = velocity_gain(0.0, -60.0, 0.0, floor);
+        assert!((util::gain_to_db(gain) + 20.0).abs() < 1e-3);
+
+        // Louder notes aren't affected by the floor
+        assert!((velocity_gain(1.0, -60.0, 0.0, floor) - 1.0).abs() < 1e-6);
+        let gain = velocity_gain(0.75, -60.0, 0.0, floor);
+        assert!((util::gain_to_db(gain) + 15.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn clamp_frequency_stays_below_nyquist() {
+        assert_eq!(clamp_frequency(440.0, 48000.0), 440.0);
+        assert_eq!(clamp_frequency(-100.0, 48000.0), 0.0);
+        assert_eq!(clamp_frequency(30000.0, 48000.0), 24000.0);
+        assert_eq!(clamp_frequency(f32::INFINITY, 48000.0), 24000.0);
+        assert_eq!(clamp_frequency(f32::NAN, 48000.0), 0.0);
+    }
+
+    #[test]
+    fn stereo_correlation_detects_phase() {
+        let left: Vec<f32> = (0..64).map(|i| (i as f32 * 0.3).sin()).collect();
+        let inverted: Vec<f32> = left.iter().map(|sample| -sample).collect();
+
+        assert!((stereo_correlation(&left, &left) - 1.0).abs() < 1e-6);
+        assert!((stereo_correlation(&left, &inverted) + 1.0).abs() < 1e-6);
+        assert_eq!(stereo_correlation(&[0.0; 64], &[0.0; 64]), 1.0);
+
+        let synth = PolyModSynth::default();
+        assert_eq!(synth.correlation().load(Ordering::Relaxed), 1.0);
+    }
+
+    #[test]
+    fn equal_loudness_gain_follows_the_a_weighting() {
+        assert!((equal_loudness_gain(1000.0) - 1.0).abs() < 1e-3);
+        // The ear is more sensitive around 4 kHz, so notes there are attenuated by about 1 dB
+        assert!((util::gain_to_db(equal_loudness_gain(4000.0)) + 0.96).abs() < 0.01);
+        // Low notes need more than the maximum boost
+        let boost = util::gain_to_db(equal_loudness_gain(100.0));
+        assert!((boost - MAX_EQUAL_LOUDNESS_BOOST_DB).abs() < 1e-3);
+    }
+
+    #[test]
+    fn constant_power_pan_keeps_the_power_constant() {
+        let value = 0.5;
+        for step in -100..=100 {
+            let pan = step as f32 / 100.0;
+            let (left, right) = constant_power_pan(value, pan);
+            let power = left * left + right * right;
+            assert!(
+                (power - 2.0 * value * value).abs() < 1e-6,
+                "pan {pan} has power {power}"
+            );
+        }
+
+        for pan in [-f32::EPSILON, 0.0, f32::EPSILON] {
+            let (left, right) = constant_power_pan(value, pan);
+            assert!((left - value).abs() < 1e-6 && (right - value).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn set_velocity_curve_sorts_and_clamps() {
+        let synth = PolyModSynth::default();
+        synth
+            .set_velocity_curve(&[(1.5, 1.0), (0.0, -0.5), (0.5, 0.25)])
+            .unwrap();
+        assert_eq!(
+            *synth.params.velocity_curve.read().unwrap(),
+            [(0.0, 0.0), (0.5, 0.25), (1.0, 1.0)]
+        );
+        assert!((synth.map_velocity(0.25) - 0.125).abs() < 1e-6);
+
+        assert_eq!(
+            synth.set_velocity_curve(&[(0.0, 0.0), (f32::NAN, 1.0)]),
+            Err(TableError::NotANumber)
+        );
+        assert_eq!(synth.params.velocity_curve.read().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn apply_velocity_curve_interpolates_between_breakpoints() {
+        let curve = [(0.25, 0.0), (0.5, 0.75), (1.0, 1.0)];
+        assert_eq!(apply_velocity_curve(&curve, 0.0), 0.0);
+        assert!((apply_velocity_curve(&curve, 0.375) - 0.375).abs() < 1e-6);
+        assert!((apply_velocity_curve(&curve, 0.75) - 0.875).abs() < 1e-6);
+        assert_eq!(apply_velocity_curve(&curve, 1.0), 1.0);
+        assert_eq!(apply_velocity_curve(&[], 0.3), 0.3);
+    }
+
+    #[test]
+    fn linear_velocity_position_is_in_decibels() {
+        let position = |velocity| velocity_position(velocity, VelocityInterpretation::Linear, 40.0);
+        assert_eq!(position(1.0), 1.0);
+        assert!((position(0.1) - 0.5).abs() < 1e-5);
+        assert!((position(0.01) - 0.0).abs() < 1e-5);
+        assert_eq!(position(0.001), 0.0);
+        assert_eq!(position(0.0), 0.0);
+
+        let perceptual = velocity_position(0.3, VelocityInterpretation::Perceptual, 40.0);
+        assert_eq!(perceptual, 0.3);
+        assert_eq!(
+            velocity_position(0.3, VelocityInterpretation::Linear, 0.0),
+            1.0
+        );
+    }
+
+    #[test]
+    fn dynamics_velocity_position_spaces_markings_evenly() {
+        let position = |velocity: f32| {
+            velocity_position(velocity / 127.0, VelocityInterpretation::Dynamics, 0.0)
+        };
+        let steps = (DYNAMICS_VELOCITIES.len() - 1) as f32;
+        for (step, &velocity) in DYNAMICS_VELOCITIES.iter().enumerate() {
+            assert!((position(velocity as f32) - step as f32 / steps).abs() < 1e-5);
+        }
+
+        // Halfway between mp and mf
+        assert!((position(72.0) - 3.5 / steps).abs() < 1e-5);
+        assert_eq!(position(0.0), 0.0);
+        assert_eq!(position(8.0), 0.0);
+    }
+
+    #[test]
+    fn set_tuning_table_retunes_notes() {
+        let mut table = [0.0; NUM_NOTES];
+        table[60] = 50.0;
+
+        // Only the retuned note is a quarter tone sharp
+        let frequency = |note| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(PolyModSynthParams::default(), &host);
+            synth.set_tuning_table(&table).unwrap();
+            host.events.push_back(note_on(0, 0, note, None));
+            let [left, _] = host.render(&mut synth, 48000);
+
+            measure_frequency(&left, host.sample_rate)
+        };
+        let expected = 440.0 * 2.0f32.powf((60.5 - 69.0) / 12.0);
+        assert!((frequency(60) - expected).abs() < 0.05);
+        assert!((frequency(61) - util::midi_note_to_freq(61)).abs() < 0.05);
+
+        let synth = PolyModSynth::default();
+        synth.set_tuning_table(&table).unwrap();
+        assert_eq!(
+            synth.set_tuning_table(&table[..127]),
+            Err(TableError::WrongLength(127))
+        );
+        table[0] = f32::NAN;
+        assert_eq!(synth.set_tuning_table(&table), Err(TableError::NotANumber));
+        assert_eq!(synth.tuning_offset(60), 50.0);
+    }
+
+    #[test]
+    fn output_stays_finite_at_the_maximum_settings() {
+        assert_finite_render(PolyModSynthParams {
+            gain: float_param(db_to_gain(12.0)),
+            velocity_range: float_param(100.0),
+            velocity_mode: EnumParam::new("", VelocityMode::Boost),
+            key_to_level: float_param(12.0),
+            equal_loudness: BoolParam::new("", true),
+            sine_wave: BoolParam::new("", false),
+            detune: float_param(50.0),
+            base_note: int_param(84),
+            onset_drift: float_param(25.0),
+            pitch_bend_range: float_param(48.0),
+            velocity_to_saw: float_param(1.0),
+            noise_level: float_param(1.0),
+            voice_drive: float_param(24.0),
+            comb_feedback: float_param(comb::MAX_FEEDBACK),
+            comb_ratio: float_param(8.0),
+            stereo_phase: float_param(90.0),
+            key_to_pan: float_param(0.5),
+            random_pan: float_param(1.0),
+            mono_pan_compensation: BoolParam::new("", true),
+            phaser_stages: int_param(phaser::MAX_STAGES as i32),
+            phaser_rate: float_param(10.0),
+            phaser_depth: float_param(1.0),
+            phaser_feedback: float_param(0.9),
+            phaser_mix: float_param(1.0),
+            effects_mix: float_param(1.0),
+            reverb_size: float_param(1.0),
+            reverb_damping: float_param(0.0),
+            eq_low_gain: float_param(18.0),
+            eq_low_frequency: float_param(20000.0),
+            eq_mid_gain: float_param(18.0),
+            eq_mid_frequency: float_param(20000.0),
+            eq_high_gain: float_param(18.0),
+            eq_high_frequency: float_param(20000.0),
+            haas_delay: float_param(haas::MAX_DELAY_MS),
+            mono_safety: BoolParam::new("", true),
+            mono_safety_threshold: float_param(1.0),
+            declick_time: float_param(0.5),
+            pedal_release_stagger: float_param(100.0),
+            retrigger_free: BoolParam::new("", true),
+            channels: std::array::from_fn(|_| ChannelParams {
+                transpose: int_param(48),
+                level: float_param(24.0),
+                ..ChannelParams::default()
+            }),
+            ..PolyModSynthParams::default()
+        });
+    }
+
+    #[test]
+    fn output_stays_finite_at_the_minimum_settings() {
+        assert_finite_render(PolyModSynthParams {
+            velocity_range: float_param(100.0),
+            velocity_mode: EnumParam::new("", VelocityMode::Centered),
+            velocity_interpretation: EnumParam::new("", VelocityInterpretation::Linear),
+            key_to_level: float_param(-12.0),
+            detune: float_param(-50.0),
+            base_note: int_param(36),
+            comb_feedback: float_param(-comb::MAX_FEEDBACK),
+            comb_ratio: float_param(0.25),
+            key_to_pan: float_param(-0.5),
+            phaser_stages: int_param(1),
+            phaser_rate: float_param(0.01),
+            phaser_feedback: float_param(-0.9),
+            phaser_mix: float_param(0.5),
+            effects_mix: float_param(0.5),
+            reverb_size: float_param(0.0),
+            reverb_damping: float_param(1.0),
+            eq_low_gain: float_param(-18.0),
+            eq_low_frequency: float_param(20.0),
+            eq_mid_gain: float_param(-18.0),
+            eq_mid_frequency: float_param(20.0),
+            eq_high_gain: float_param(-18.0),
+            eq_high_frequency: float_param(20.0),
+            mono_safety: BoolParam::new("", true),
+            mono_safety_threshold: float_param(-1.0),
+            true_peak_limit: BoolParam::new("", true),
+            true_peak_ceiling: float_param(-12.0),
+            declick_time: float_param(20.0),
+            drone_mode: BoolParam::new("", true),
+            max_note_length: float_param(0.001),
+            channels: std::array::from_fn(|_| ChannelParams {
+                transpose: int_param(-48),
+                level: float_param(-24.0),
+                pan: float_param(-1.0),
+                ..ChannelParams::default()
+            }),
+            ..PolyModSynthParams::default()
+        });
+    }
+
+    #[test]
+    fn boosting_the_gain_only_clips_above_full_scale() {
+        let render = |gain_db: f32, level_db: f32| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    gain: float_param(db_to_gain(gain_db)),
+                    channels: std::array::from_fn(|_| ChannelParams {
+                        level: float_param(level_db),
+                        ..ChannelParams::default()
+                    }),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events.push_back(note_on(0, 0, 69, None));
+            let [left, _] = host.render(&mut synth, 4800);
+            let left = &left[480..];
+
+            let peak = left
+                .iter()
+                .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+            let rms =
+                (left.iter().map(|sample| sample * sample).sum::<f32>() / left.len() as f32).sqrt();
+
+            (peak, rms)
+        };
+
+        // At low levels +6 dB doubles both the peak and the RMS level
+        let (quiet_peak, quiet_rms) = render(0.0, -20.0);
+        let (boosted_peak, boosted_rms) = render(6.0, -20.0);
+        assert!((boosted_peak / quiet_peak - db_to_gain(6.0)).abs() < 1e-3);
+        assert!((boosted_rms / quiet_rms - db_to_gain(6.0)).abs() < 1e-3);
+
+        // But a note at full scale can't go past it
+        assert!((render(0.0, 0.0).0 - 1.0).abs() < 1e-3);
+        assert_eq!(render(6.0, 0.0).0, 1.0);
+    }
+
+    #[test]
+    fn phases_reset_when_playback_starts() {
+        for reset_on_play in [false, true] {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    reset_on_play: BoolParam::new("", reset_on_play),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            let index = voice_index(0, 69).unwrap();
+            let start_phase = synth.start_phase(true);
+
+            host.events.push_back(note_on(0, 0, 69, None));
+            host.render(&mut synth, 100);
+            let free_running_phase = synth.voices[index].phase;
+            assert_ne!(free_running_phase, start_phase);
+
+            host.playing = true;
+            host.render(&mut synth, 0);
+            let expected = if reset_on_play {
+                start_phase
+            } else {
+                free_running_phase
+            };
+            assert_eq!(synth.voices[index].phase, expected);
+
+            // Only the start of playback resets the phases
+            host.render(&mut synth, 100);
+            let phase = synth.voices[index].phase;
+            host.render(&mut synth, 0);
+            assert_eq!(synth.voices[index].phase, phase);
+        }
+    }
+
+    #[test]
+    fn buffer_size_does_not_change_the_output() {
+        for buffer_len in [1, 63, 64, 65, 128] {
+            let num_buffers = 512usize.div_ceil(buffer_len);
+            let event = |buffer_idx: usize, timing: u32| {
+                if buffer_idx.is_multiple_of(2) {
+                    let note = 60 + (buffer_idx / 2 % 12) as u8;
+                    note_on(timing, 0, note, Some(buffer_idx as i32))
+                } else {
+                    NoteEvent::MidiPitchBend {
+                        timing,
+                        channel: 0,
+                        value: (buffer_idx % 8) as f32 / 8.0,
+                    }
+                }
+            };
+
+            // The same events rendered in a single buffer
+            let mut host = TestHost::default();
+            let mut synth = test_synth(PolyModSynthParams::default(), &host);
+            host.events
+                .extend((0..num_buffers).map(|idx| event(idx, (idx * buffer_len) as u32)));
+            let [expected_left, expected_right] = host.render(&mut synth, buffer_len * num_buffers);
+
+            let mut host = TestHost::default();
+            let mut synth = test_synth(PolyModSynthParams::default(), &host);
+            let (mut left, mut right) = (Vec::new(), Vec::new());
+            for buffer_idx in 0..num_buffers {
+                host.events.push_back(event(buffer_idx, 0));
+                let [buffer_left, buffer_right] = host.render(&mut synth, buffer_len);
+                left.extend(buffer_left);
+                right.extend(buffer_right);
+            }
+
+            assert_eq!(left, expected_left, "buffer length {buffer_len}");
+            assert_eq!(right, expected_right, "buffer length {buffer_len}");
+            assert!(left.iter().any(|&sample| sample != 0.0));
+        }
+    }
+
+    #[test]
+    fn diagnostics_count_voices() {
+        let mut host = TestHost::default();
+        let mut synth = test_synth(PolyModSynthParams::default(), &host);
+        let diagnostics = synth.diagnostics();
+
+        host.events.extend([
+            note_on(0, 0, 60, None),
+            note_on(0, 1, 60, None),
+            note_on(10, 0, 64, None),
+            note_on(20, 0, 60, None),
+        ]);
+        host.render(&mut synth, 64);
+        assert_eq!(diagnostics.active_voices(), 3);
+
+        host.events.push_back(note_off(0, 1, 60));
+        host.render(&mut synth, 48000);
+        assert_eq!(diagnostics.active_voices(), 2);
+        assert_eq!(diagnostics.notes_per_second(), 4);
+        assert_eq!(diagnostics.steals_per_second(), 1);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn metronome_clicks_on_the_beat() {
+        // At 120 BPM there's a beat every 24000 samples, and the song starts halfway into a beat
+        let mut host = TestHost {
+            tempo: Some(120.0),
+            pos_beats: Some(0.5),
+            playing: true,
+            ..TestHost::default()
+        };
+        let mut synth = test_synth(
+            PolyModSynthParams {
+                metronome: BoolParam::new("", true),
+                ..PolyModSynthParams::default()
+            },
+            &host,
+        );
+        host.events.push_back(note_on(0, 0, 69, None));
+
+        let mut left = Vec::new();
+        for _ in 0..100 {
+            left.extend(host.render(&mut synth, 500)[0].iter().copied());
+        }
+
+        // The clicks are sines starting at zero, and the voices are muted in between
+        let click_starts: Vec<_> = (1..left.len())
+            .filter(|&idx| left[idx - 1] == 0.0 && left[idx] != 0.0)
+            .map(|idx| idx - 1)
+            .collect();
+        assert_eq!(click_starts.len(), 2);
+        for (click_start, expected) in click_starts.into_iter().zip([12000, 36000]) {
+            assert!(
+                click_start.abs_diff(expected) <= 1,
+                "click at {click_start}"
+            );
+        }
+    }
+
+    /// Render `events` with `params`, followed by another second of silence, and return the
+    /// `(voice_id, channel, note)` of every voice the host was told was terminated.
+    fn terminated_voices(
+        params: PolyModSynthParams,
+        events: impl IntoIterator<Item = PluginNoteEvent<PolyModSynth>>,
+    ) -> Vec<(Option<i32>, u8, u8)> {
+        let mut host = TestHost::default();
+        let mut synth = test_synth(params, &host);
+        host.events.extend(events);
+        host.render(&mut synth, 512);
+        host.render(&mut synth, 48000);
+
+        host.sent_events
+            .iter()
+            .filter_map(|event| match *event {
+                NoteEvent::VoiceTerminated {
+                    voice_id,
+                    channel,
+                    note,
+                    ..
+                } => Some((voice_id, channel, note)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn note_off_terminates_the_voice_once() {
+        let terminated = terminated_voices(
+            PolyModSynthParams::default(),
+            [
+                note_on(0, 2, 60, Some(7)),
+                note_on(0, 2, 64, Some(8)),
+                note_off(100, 2, 60),
+                note_off(200, 2, 60),
+            ],
+        );
+        assert_eq!(terminated, [(Some(7), 2, 60)]);
+    }
+
+    #[test]
+    fn choke_terminates_the_voice_once() {
+        let choke = |timing| NoteEvent::Choke {
+            timing,
+            voice_id: Some(7),
+            channel: 2,
+            note: 60,
+        };
+        let terminated = terminated_voices(
+            PolyModSynthParams::default(),
+            [note_on(0, 2, 60, Some(7)), choke(100), choke(200)],
+        );
+        assert_eq!(terminated, [(Some(7), 2, 60)]);
+    }
+
+    #[test]
+    fn all_notes_off_terminates_the_channel_once() {
+        let all_notes_off = |timing| NoteEvent::MidiCC {
+            timing,
+            channel: 2,
+            cc: ALL_NOTES_OFF,
+            value: 0.0,
+        };
+        let terminated = terminated_voices(
+            PolyModSynthParams::default(),
+            [
+                note_on(0, 2, 60, Some(7)),
+                note_on(0, 3, 60, Some(8)),
+                all_notes_off(100),
+                all_notes_off(200),
+            ],
+        );
+        assert_eq!(terminated, [(Some(7), 2, 60)]);
+    }
+
+    #[test]
+    fn stealing_terminates_the_old_voice_once() {
+        let terminated = terminated_voices(
+            PolyModSynthParams::default(),
+            [note_on(0, 2, 60, Some(7)), note_on(100, 2, 60, Some(8))],
+        );
+        assert_eq!(terminated, [(Some(7), 2, 60)]);
+
+        let terminated = terminated_voices(
+            PolyModSynthParams::default(),
+            [note_on(0, 2, 60, None), note_on(100, 2, 60, None)],
+        );
+        assert_eq!(terminated, [(None, 2, 60)]);
+    }
+
+    #[test]
+    fn delayed_releases_terminate_the_voice_once() {
+        // Notes held for longer than the maximum note length
+        let terminated = terminated_voices(
+            PolyModSynthParams {
+                max_note_length: float_param(0.5),
+                ..PolyModSynthParams::default()
+            },
+            [note_on(0, 2, 60, Some(7))],
+        );
+        assert_eq!(terminated, [(Some(7), 2, 60)]);
+
+        // Notes released by lifting the sostenuto pedal, spread out over the stagger time
+        let sostenuto = |timing, value| NoteEvent::MidiCC {
+            timing,
+            channel: 2,
+            cc: SUSTENUTO,
+            value,
+        };
+        let terminated = terminated_voices(
+            PolyModSynthParams {
+                pedal_release_stagger: float_param(5.0),
+                ..PolyModSynthParams::default()
+            },
+            [
+                note_on(0, 2, 60, Some(7)),
+                note_on(0, 2, 64, Some(8)),
+                sostenuto(10, 1.0),
+                note_off(20, 2, 60),
+                note_off(20, 2, 64),
+                sostenuto(30, 0.0),
+                note_off(400, 2, 60),
+            ],
+        );
+        assert_eq!(terminated, [(Some(7), 2, 60), (Some(8), 2, 64)]);
+    }
+
+    #[test]
+    fn voices_are_summed_without_accumulating_rounding_errors() {
+        let render = |notes: &[u8]| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(PolyModSynthParams::default(), &host);
+            host.events
+                .extend(notes.iter().map(|&note| note_on(0, 0, note, None)));
+            let [left, _] = host.render(&mut synth, 4800);
+
+            left
+        };
+
+        let notes: Vec<u8> = (40..72).collect();
+        let combined = render(&notes);
+        let mut sum = vec![0.0f64; combined.len()];
+        for &note in &notes {
+            for (sum, sample) in sum.iter_mut().zip(render(&[note])) {
+                *sum += sample as f64;
+            }
+        }
+
+        // Past the soft start, the combined render is the exact sum of the voices rounded to
+        // single precision once, so it's off by at most half an ULP
+        for (&combined, &sum) in combined.iter().zip(&sum).skip(480) {
+            assert!((combined as f64 - sum).abs() <= sum.abs() * f32::EPSILON as f64 / 2.0);
+        }
+    }
+
+    #[test]
+    fn soft_start_lasts_for_the_declick_time() {
+        for (declick_time, ramp_samples) in [(2.0, 96), (10.0, 480)] {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    declick_time: float_param(declick_time),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+
+            host.render(&mut synth, ramp_samples / 2);
+            assert!((synth.soft_start_gain - 0.5).abs() < 1e-3);
+            host.render(&mut synth, ramp_samples / 2 - 1);
+            assert!(synth.soft_start_gain < 1.0);
+            host.render(&mut synth, 1);
+            assert_eq!(synth.soft_start_gain, 1.0);
+        }
+    }
+
+    #[test]
+    fn events_for_channels_past_16_are_ignored() {
+        let render = |events: Vec<PluginNoteEvent<PolyModSynth>>| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    onset_drift: float_param(10.0),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events.extend(events);
+            let output = host.render(&mut synth, 4800);
+
+            (output, synth.diagnostics().active_voices())
+        };
+        let valid_note = note_on(40, 0, 64, Some(2));
+        let expected = render(vec![valid_note]);
+
+        // The events on the invalid channel don't play anything, and they don't change how much
+        // the valid note drifts either
+        for channel in [16, u8::MAX] {
+            let events = vec![
+                note_on(0, channel, 60, Some(1)),
+                NoteEvent::MidiCC {
+                    timing: 10,
+                    channel,
+                    cc: SUSTENUTO,
+                    value: 1.0,
+                },
+                NoteEvent::MidiPitchBend {
+                    timing: 20,
+                    channel,
+                    value: 1.0,
+                },
+                note_off(30, channel, 60),
+                valid_note,
+            ];
+            assert_eq!(render(events), expected, "channel {channel}");
+        }
+        assert_eq!(expected.1, 1);
+    }
+
+    #[test]
+    fn stereo_phase_offsets_the_right_channel() {
+        let render = |stereo_phase| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    gain: float_param(1.0),
+                    stereo_phase: float_param(stereo_phase),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events.push_back(note_on(0, 0, 69, None));
+
+            host.render(&mut synth, 4800)
+        };
+
+        let [left, right] = render(0.0);
+        assert_eq!(left, right);
+
+        // With a quarter cycle offset the right channel is a cosine where the left channel is a
+        // sine, so their squares always add up to the same power
+        let [left, right] = render(90.0);
+        assert_ne!(left, right);
+        let power: Vec<f32> = left
+            .iter()
+            .zip(&right)
+            .skip(480)
+            .map(|(left, right)| left * left + right * right)
+            .collect();
+        assert!(power.iter().all(|sample| (sample - power[0]).abs() < 1e-4));
+        assert!((power[0] - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn zero_velocity_note_on_releases_the_voice() {
+        let zero_velocity_note_on = NoteEvent::NoteOn {
+            timing: 100,
+            voice_id: None,
+            channel: 2,
+            note: 60,
+            velocity: 0.0,
+        };
+
+        let mut host = TestHost::default();
+        let mut synth = test_synth(PolyModSynthParams::default(), &host);
+        host.events
+            .extend([note_on(0, 2, 60, Some(7)), zero_velocity_note_on]);
+        let [left, _] = host.render(&mut synth, 4800);
+
+        assert!(left[..100].iter().any(|&sample| sample != 0.0));
+        assert!(left[100..].iter().all(|&sample| sample == 0.0));
+        assert_eq!(synth.diagnostics().active_voices(), 0);
+        assert!(matches!(
+            host.sent_events[..],
+            [NoteEvent::VoiceTerminated {
+                timing: 100,
+                voice_id: Some(7),
+                channel: 2,
+                note: 60,
+            }]
+        ));
+    }
+
+    #[test]
+    fn switching_waveforms_mid_note_crossfades() {
+        let params = |sine_wave| PolyModSynthParams {
+            gain: float_param(1.0),
+            sine_wave: BoolParam::new("", sine_wave),
+            ..PolyModSynthParams::default()
+        };
+        let mut host = TestHost::default();
+        let mut synth = test_synth(params(true), &host);
+        host.events.push_back(note_on(0, 0, 69, None));
+        let [mut left, _] = host.render(&mut synth, 4800);
+
+        synth.params = Arc::new(params(false));
+        let switch_idx = left.len();
+        left.extend(host.render(&mut synth, 4800)[0].iter().copied());
+
+        // Right at the switch the output still moves like the sine, and the other waveform's steps
+        // (up to a full 2 when it wraps from 1 to -1) are only faded in over the 5 ms declick time
+        let crossfade_samples = 240;
+        let max_sine_step = TAU * 440.0 / host.sample_rate;
+        for (offset, pair) in left[switch_idx - 1..]
+            .windows(2)
+            .take(crossfade_samples)
+            .enumerate()
+        {
+            let step = (pair[1] - pair[0]).abs();
+            let max_step = max_sine_step + 2.0 * (offset + 1) as f32 / crossfade_samples as f32;
+            assert!(step <= max_step + 1e-3, "step of {step} at {offset}");
+        }
+
+        // Afterwards only the new waveform is left
+        let tail = &left[switch_idx + crossfade_samples..];
+        assert!(
+            tail.iter()
+                .all(|sample| (sample - sample.round()).abs() < 1e-3)
+        );
+    }
+
+    #[test]
+    fn stolen_voices_ramp_down() {
+        let mut host = TestHost::default();
+        let mut synth = test_synth(
+            PolyModSynthParams {
+                gain: float_param(1.0),
+                ..PolyModSynthParams::default()
+            },
+            &host,
+        );
+
+        // The new note is muted, so only the old note fading out is left after the steal
+        host.events.extend([
+            note_on(0, 0, 69, Some(1)),
+            note_on(1000, 0, 69, Some(2)),
+            NoteEvent::PolyVolume {
+                timing: 1000,
+                voice_id: Some(2),
+                channel: 0,
+                note: 69,
+                gain: 0.0,
+            },
+        ]);
+        let [left, _] = host.render(&mut synth, 4800);
+
+        // It fades out over the 5 ms declick time without any jumps
+        let fade_samples = 240;
+        let max_sine_step = TAU * 440.0 / host.sample_rate;
+        assert!(
+            left[999..1000 + fade_samples]
+                .windows(2)
+                .all(|pair| (pair[1] - pair[0]).abs() <= max_sine_step + 1e-3)
+        );
+        for (offset, sample) in left[1000..=1000 + fade_samples].iter().enumerate() {
+            assert!(sample.abs() <= 1.0 - offset as f32 / fade_samples as f32 + 1e-3);
+        }
+        assert!(
+            left[1001 + fade_samples..]
+                .iter()
+                .all(|&sample| sample == 0.0)
+        );
+    }
+
+    #[test]
+    fn reference_tone_replaces_the_voices() {
+        let mut host = TestHost::default();
+        let mut synth = test_synth(
+            PolyModSynthParams {
+                gain: float_param(1.0),
+                reference_tone: BoolParam::new("", true),
+                reference_note: int_param(69),
+                ..PolyModSynthParams::default()
+            },
+            &host,
+        );
+        host.events
+            .extend([note_on(0, 0, 60, None), note_on(0, 0, 64, None)]);
+        let [left, right] = host.render(&mut synth, 48000);
+
+        // A steady sine at the reference note, at the note gain
+        let frequency = measure_frequency(&left, host.sample_rate);
+        assert!((frequency - 440.0).abs() < 0.05, "{frequency} Hz");
+        let peak = left[480..]
+            .iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        assert!((peak - 1.0).abs() < 1e-3);
+        assert_eq!(left, right);
+
+        // The notes are still tracked so they can be released
+        assert_eq!(synth.diagnostics().active_voices(), 2);
+    }
+
+    #[test]
+    fn sostenuto_only_holds_notes_that_were_down() {
+        let sostenuto = |timing, value| NoteEvent::MidiCC {
+            timing,
+            channel: 0,
+            cc: SUSTENUTO,
+            value,
+        };
+        let mut host = TestHost::default();
+        let mut synth = test_synth(PolyModSynthParams::default(), &host);
+        host.events.extend([
+            note_on(0, 0, 60, Some(1)),
+            sostenuto(100, 1.0),
+            note_on(200, 0, 64, Some(2)),
+            note_off(300, 0, 64),
+            note_off(400, 0, 60),
+            sostenuto(1000, 0.0),
+        ]);
+        let [left, _] = host.render(&mut synth, 4800);
+
+        // The note played after the pedal went down stops when it's released, and the held note
+        // keeps ringing until the pedal comes back up
+        let terminated: Vec<_> = host
+            .sent_events
+            .iter()
+            .filter_map(|event| match *event {
+                NoteEvent::VoiceTerminated {
+                    timing, voice_id, ..
+                } => Some((timing, voice_id)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(terminated, [(300, Some(2)), (1000, Some(1))]);
+        assert!(left[400..1000].iter().any(|&sample| sample != 0.0));
+        assert!(left[1000..].iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn channel_transpose_shifts_only_that_channel() {
+        let frequency = |channel| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    channels: std::array::from_fn(|channel| ChannelParams {
+                        transpose: int_param(if channel == 1 { 12 } else { 0 }),
+                        ..ChannelParams::default()
+                    }),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events.push_back(note_on(0, channel, 69, None));
+            let [left, _] = host.render(&mut synth, 48000);
+
+            measure_frequency(&left, host.sample_rate)
+        };
+
+        assert!((frequency(0) - 440.0).abs() < 0.05);
+        assert!((frequency(1) - 880.0).abs() < 0.05);
+    }
+
+    /// Render a second of audio with `notes` held on each of the first `num_channels` channels, and
+    /// return how long that took.
+    fn render_time(num_channels: usize, notes: std::ops::Range<u8>) -> std::time::Duration {
+        let mut host = TestHost::default();
+        let mut synth = test_synth(PolyModSynthParams::default(), &host);
+        for channel in 0..num_channels as u8 {
+            host.events
+                .extend(notes.clone().map(|note| note_on(0, channel, note, None)));
+        }
+        host.render(&mut synth, 512);
+        assert_eq!(
+            synth.diagnostics().active_voices() as usize,
+            num_channels * notes.len()
+        );
+
+        let start = std::time::Instant::now();
+        host.render(&mut synth, host.sample_rate as usize);
+
+        start.elapsed()
+    }
+
+    /// Timings aren't reliable in debug builds or on a busy machine, so this only runs when asked
+    /// for with `cargo test --release -- --ignored`.
+    #[test]
+    #[ignore]
+    fn render_time_scales_with_the_active_voices() {
+        // Every voice playing at once is the worst case
+        let all_voices = render_time(NUM_CHANNELS, 0..NUM_NOTES as u8);
+        println!("a second with every voice active took {all_voices:?} to render");
+
+        // Only the active voices are visited, so a couple of notes cost a fraction of that
+        let two_voices = render_time(1, 60..62);
+        assert!(
+            two_voices * 100 < all_voices,
+            "a second with two voices active took {two_voices:?} to render"
+        );
+    }
+
+    #[test]
+    fn max_note_length_only_releases_long_notes() {
+        let render = |max_note_length, events: &[PluginNoteEvent<PolyModSynth>]| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    max_note_length: float_param(max_note_length),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events.extend(events.iter().copied());
+            let [left, _] = host.render(&mut synth, 48000);
+
+            (left, synth.diagnostics().active_voices())
+        };
+
+        // A note released before the limit sounds exactly the same as without one
+        let short_note = [note_on(0, 0, 60, None), note_off(12000, 0, 60)];
+        assert_eq!(render(0.5, &short_note), render(0.0, &short_note));
+
+        // A note held past it is released once it's half a second old
+        let (left, active_voices) = render(0.5, &[note_on(0, 0, 60, None)]);
+        assert_eq!(active_voices, 0);
+        let last_sample = left.iter().rposition(|&sample| sample != 0.0).unwrap();
+        assert!((24000 - 64..24000 + 64).contains(&last_sample));
+        assert_eq!(render(0.0, &[note_on(0, 0, 60, None)]).1, 1);
+    }
+
+    #[test]
+    fn noise_energy_follows_the_noise_level() {
+        let render = |noise_level| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    gain: float_param(1.0),
+                    noise_level: float_param(noise_level),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events.push_back(note_on(0, 0, 69, None));
+            let [left, _] = host.render(&mut synth, 4800);
+
+            left
+        };
+
+        // The noise is blended in, so taking away the attenuated sine leaves just the noise
+        let sine = render(0.0);
+        let noise_energy = |noise_level: f32| -> f32 {
+            render(noise_level)
+                .iter()
+                .zip(&sine)
+                .map(|(sample, sine)| (sample - sine * (1.0 - noise_level)).powi(2))
+                .sum()
+        };
+
+        assert!(noise_energy(0.25) > 0.0);
+        assert!((noise_energy(0.5) / noise_energy(0.25) - 4.0).abs() < 1e-3);
+        assert!((noise_energy(1.0) / noise_energy(0.25) - 16.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn routed_channels_only_play_on_their_aux_output() {
+        let render = |output| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    gain: float_param(db_to_gain(6.0)),
+                    invert_left: BoolParam::new("", true),
+                    channels: std::array::from_fn(|_| ChannelParams {
+                        output: int_param(output),
+                        ..ChannelParams::default()
+                    }),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events.push_back(note_on(0, 0, 69, None));
+
+            host.render_with_aux(&mut synth, 4800, NUM_AUX_OUTPUTS)
+        };
+
+        // The aux output goes through the same soft start, ceiling, and inversion as the main
+        // output would have
+        let main = render(0).swap_remove(0);
+        let outputs = render(2);
+        for (idx, [left, right]) in outputs.iter().enumerate() {
+            if idx == 2 {
+                assert_eq!([left, right], [&main[0], &main[1]]);
+            } else {
+                assert!(left.iter().chain(right).all(|&sample| sample == 0.0));
+            }
+        }
+        assert!(main[0].iter().any(|&sample| sample != 0.0));
+    }
+
+    #[test]
+    fn drone_mode_ignores_note_offs() {
+        for drone_mode in [false, true] {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    drone_mode: BoolParam::new("", drone_mode),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events
+                .extend([note_on(0, 0, 69, Some(1)), note_off(100, 0, 69)]);
+            let [left, _] = host.render(&mut synth, 4800);
+
+            let still_playing = left[4700..].iter().any(|&sample| sample != 0.0);
+            assert_eq!(still_playing, drone_mode);
+            assert_eq!(host.sent_events.is_empty(), drone_mode);
+        }
+    }
+
+    #[test]
+    fn pan_compensation_keeps_the_mono_sum_level() {
+        let mono_peak = |mono_pan_compensation, pan| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    gain: float_param(0.5),
+                    mono_pan_compensation: BoolParam::new("", mono_pan_compensation),
+                    channels: std::array::from_fn(|_| ChannelParams {
+                        pan: float_param(pan),
+                        ..ChannelParams::default()
+                    }),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events.push_back(note_on(0, 0, 69, None));
+            let [left, right] = host.render(&mut synth, 4800);
+
+            left.iter()
+                .zip(&right)
+                .skip(480)
+                .fold(0.0f32, |peak, (left, right)| peak.max((left + right).abs()))
+        };
+
+        let center = mono_peak(true, 0.0);
+        assert!((center - 1.0).abs() < 1e-3);
+        for pan in [-1.0, -0.5, 0.25, 1.0] {
+            let peak = mono_peak(true, pan);
+            assert!((peak - center).abs() < 1e-3, "pan {pan} peaks at {peak}");
+        }
+
+        // Without the compensation the constant power law drops the mono sum by 3 dB at the sides
+        let side = mono_peak(false, 1.0);
+        assert!((side - center / 2.0f32.sqrt()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn expression_scales_the_level_smoothly() {
+        let render = |events: &[PluginNoteEvent<PolyModSynth>]| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(PolyModSynthParams::default(), &host);
+            host.events.extend(events.iter().copied());
+            let [left, _] = host.render(&mut synth, 4800);
+
+            left
+        };
+        let unchanged = render(&[note_on(0, 0, 69, None)]);
+        let expression = render(&[
+            note_on(0, 0, 69, None),
+            NoteEvent::MidiCC {
+                timing: 1000,
+                channel: 0,
+                cc: EXPRESSION_CONTROLLER_MSB,
+                value: 0.5,
+            },
+        ]);
+
+        // The gain ramps down to half over the 10 ms smoothing time instead of jumping there
+        let gain: Vec<(usize, f32)> = expression
+            .iter()
+            .zip(&unchanged)
+            .enumerate()
+            .filter(|(_, (_, unchanged))| unchanged.abs() > 1e-3)
+            .map(|(idx, (sample, unchanged))| (idx, sample / unchanged))
+            .collect();
+        let ramp_step = 0.5 / 480.0;
+        for &(idx, gain) in &gain {
+            let expected = match idx {
+                ..1000 => 1.0,
+                1000..1480 => 1.0 - (idx - 999) as f32 * ramp_step,
+                _ => 0.5,
+            };
+            assert!((gain - expected).abs() < 1e-3, "gain {gain} at {idx}");
+        }
+    }
+
+    #[test]
+    fn boost_mode_raises_loud_notes_above_unity() {
+        let peak = |velocity_mode, velocity| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    gain: float_param(0.1),
+                    velocity_range: float_param(12.0),
+                    velocity_mode: EnumParam::new("", velocity_mode),
+                    velocity_interpretation: EnumParam::new("", VelocityInterpretation::Perceptual),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events.push_back(NoteEvent::NoteOn {
+                timing: 0,
+                voice_id: None,
+                channel: 0,
+                note: 69,
+                velocity,
+            });
+            let [left, _] = host.render(&mut synth, 4800);
+
+            left[480..]
+                .iter()
+                .fold(0.0f32, |peak, sample| peak.max(sample.abs()))
+        };
+
+        // Full velocity is 12 dB above the note gain, and softer notes are boosted less
+        assert!((peak(VelocityMode::Boost, 1.0) - 0.1 * db_to_gain(12.0)).abs() < 1e-3);
+        assert!((peak(VelocityMode::Boost, 0.5) - 0.1 * db_to_gain(6.0)).abs() < 1e-3);
+        assert!((peak(VelocityMode::Attenuate, 1.0) - 0.1).abs() < 1e-3);
+    }
+
+    #[test]
+    fn retrigger_free_notes_keep_their_phase() {
+        let render = |retrigger_free, events: &[PluginNoteEvent<PolyModSynth>]| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    retrigger_free: BoolParam::new("", retrigger_free),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events.extend(events.iter().copied());
+
+            host.render(&mut synth, 4800)
+        };
+        let single = [note_on(0, 0, 69, Some(1))];
+        let repeated = [note_on(0, 0, 69, Some(1)), note_on(1000, 0, 69, Some(2))];
+
+        // Playing the note again just carries on with the same waveform
+        assert_eq!(render(true, &repeated), render(true, &single));
+        assert_ne!(render(false, &repeated), render(false, &single));
+    }
+
+    #[test]
+    fn pitch_bend_range_reaches_two_octaves() {
+        let frequency = |bend| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    pitch_bend_range: float_param(24.0),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events.extend([
+                NoteEvent::MidiPitchBend {
+                    timing: 0,
+                    channel: 0,
+                    value: bend,
+                },
+                note_on(0, 0, 69, None),
+            ]);
+            let [left, _] = host.render(&mut synth, 48000);
+
+            measure_frequency(&left, host.sample_rate)
+        };
+
+        assert!((frequency(1.0) - 1760.0).abs() < 0.1);
+        assert!((frequency(0.5) - 440.0).abs() < 0.05);
+        assert!((frequency(0.0) - 110.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn onset_drift_settles_back_to_the_note() {
+        let render = |onset_drift| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    onset_drift: float_param(onset_drift),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events.push_back(note_on(0, 0, 69, None));
+            let [left, _] = host.render(&mut synth, 48000);
+
+            left
+        };
+        let steady = render(0.0);
+        let drifting = render(25.0);
+
+        // The drift shifts the waveform while it's settling, but after that it's back at the
+        // note's pitch with a constant phase offset
+        let shifts: Vec<f32> = rising_crossings(&drifting)
+            .iter()
+            .zip(rising_crossings(&steady))
+            .map(|(drifting, steady)| drifting - steady)
+            .collect();
+        let settled_shift = shifts[shifts.len() - 1];
+        assert!(
+            settled_shift.abs() > 1.0,
+            "shifted by {settled_shift} samples"
+        );
+        assert!(
+            shifts[200..]
+                .iter()
+                .all(|shift| (shift - settled_shift).abs() < 0.01)
+        );
+        assert!((measure_frequency(&drifting[24000..], 48000.0) - 440.0).abs() < 0.05);
+
+        // Without any drift the note is at its pitch right from the start
+        assert!((measure_frequency(&steady[..960], 48000.0) - 440.0).abs() < 0.05);
+        assert!((measure_frequency(&drifting[..960], 48000.0) - 440.0).abs() > 1.0);
+    }
+
+    #[test]
+    fn pedal_releases_are_staggered() {
+        let release_timings = |pedal_release_stagger| {
+            let sostenuto = |timing, value| NoteEvent::MidiCC {
+                timing,
+                channel: 0,
+                cc: SUSTENUTO,
+                value,
+            };
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    pedal_release_stagger: float_param(pedal_release_stagger),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events.extend([
+                note_on(0, 0, 60, None),
+                note_on(0, 0, 64, None),
+                note_on(0, 0, 67, None),
+                sostenuto(10, 1.0),
+                note_off(20, 0, 60),
+                note_off(20, 0, 64),
+                note_off(20, 0, 67),
+                sostenuto(1000, 0.0),
+            ]);
+            host.render(&mut synth, 4800);
+
+            host.sent_events
+                .iter()
+                .filter_map(|event| match *event {
+                    NoteEvent::VoiceTerminated { timing, .. } => Some(timing),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(release_timings(0.0), [1000, 1000, 1000]);
+
+        // 10 ms spread over three notes is 240 samples between each release. The later releases
+        // happen at the start of a block, so they can be up to a block late.
+        let timings = release_timings(10.0);
+        assert_eq!(timings.len(), 3);
+        for (idx, timing) in timings.into_iter().enumerate() {
+            let expected = 1000 + idx as u32 * 240;
+            assert!(
+                (expected..expected + MAX_BLOCK_SIZE as u32).contains(&timing),
+                "release {idx} at {timing}"
+            );
+        }
+    }
+
+    #[test]
+    fn notes_start_at_the_start_phase() {
+        let first_sample = |sine_wave, start_phase: Option<f32>| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    gain: float_param(1.0),
+                    sine_wave: BoolParam::new("", sine_wave),
+                    start_phase_override: BoolParam::new("", start_phase.is_some()),
+                    start_phase: float_param(start_phase.unwrap_or(0.0)),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            // Past the soft start
+            host.render(&mut synth, 480);
+            host.events.push_back(note_on(0, 0, 69, None));
+
+            host.render(&mut synth, 1)[0][0]
+        };
+
+        // By default both waveforms start at a zero crossing
+        assert_eq!(first_sample(true, None), 0.0);
+        assert_eq!(first_sample(false, None), 0.0);
+
+        assert!((first_sample(true, Some(90.0)) - 1.0).abs() < 1e-6);
+        assert!((first_sample(true, Some(270.0)) + 1.0).abs() < 1e-6);
+        assert_eq!(first_sample(false, Some(0.0)), -1.0);
+        assert_eq!(first_sample(false, Some(270.0)), 1.0);
+    }
+
+    #[test]
+    fn mono_outputs_are_refused_or_skipped() {
+        let mut host = TestHost::default();
+        let buffer_config = BufferConfig {
+            sample_rate: host.sample_rate,
+            min_buffer_size: None,
+            max_buffer_size: 512,
+            process_mode: ProcessMode::Realtime,
+        };
+        let mut synth = PolyModSynth {
+            params: Arc::new(PolyModSynthParams {
+                channels: std::array::from_fn(|_| ChannelParams {
+                    output: int_param(1),
+                    ..ChannelParams::default()
+                }),
+                ..PolyModSynthParams::default()
+            }),
+            ..PolyModSynth::default()
+        };
+        for layout in PolyModSynth::AUDIO_IO_LAYOUTS {
+            assert!(synth.initialize(layout, &buffer_config, &mut host));
+        }
+        let mono_layout = AudioIOLayout {
+            main_output_channels: NonZeroU32::new(1),
+            ..PolyModSynth::AUDIO_IO_LAYOUTS[1]
+        };
+        assert!(!synth.initialize(&mono_layout, &buffer_config, &mut host));
+
+        // Channels routed to a mono aux output play through the main output instead
+        synth.reset();
+        let mut aux_left = vec![0.0; 512];
+        let mut main = [vec![0.0; 512], vec![0.0; 512]];
+        let [left, right] = &mut main;
+        host.events.push_back(note_on(0, 0, 69, None));
+        synth.process_buffer(&mut [left, right], &mut [&mut [&mut aux_left]], &mut host);
+
+        assert!(main[0].iter().any(|&sample| sample != 0.0));
+        assert!(aux_left.iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn loud_notes_are_brighter_with_velocity_to_saw() {
+        // The second harmonic relative to the fundamental. Sines don't have one, and a saw's is at
+        // half the fundamental's level.
+        let brightness = |velocity| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    velocity_to_saw: float_param(1.0),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events.push_back(NoteEvent::NoteOn {
+                timing: 0,
+                voice_id: None,
+                channel: 0,
+                note: 69,
+                velocity,
+            });
+            let [left, _] = host.render(&mut synth, 48000);
+
+            // A whole number of cycles past the soft start
+            let samples = &left[4800..];
+            tone_amplitude(samples, 880.0, host.sample_rate)
+                / tone_amplitude(samples, 440.0, host.sample_rate)
+        };
+
+        let soft = brightness(0.1);
+        let loud = brightness(1.0);
+        assert!(soft < 0.1, "soft notes have a brightness of {soft}");
+        assert!(
+            (loud - 0.5).abs() < 1e-2,
+            "loud notes have a brightness of {loud}"
+        );
+    }
+
+    #[test]
+    fn invert_only_negates_the_selected_channel() {
+        let render = |invert_left, invert_right| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    stereo_phase: float_param(90.0),
+                    invert_left: BoolParam::new("", invert_left),
+                    invert_right: BoolParam::new("", invert_right),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events.push_back(note_on(0, 0, 69, None));
+
+            host.render(&mut synth, 4800)
+        };
+        let negated = |samples: &[f32]| samples.iter().map(|sample| -sample).collect::<Vec<_>>();
+
+        let [left, right] = render(false, false);
+        assert_eq!(render(true, false), [negated(&left), right.clone()]);
+        assert_eq!(render(false, true), [left.clone(), negated(&right)]);
+        assert_eq!(render(true, true), [negated(&left), negated(&right)]);
+    }
+
+    #[test]
+    fn random_pan_seed_makes_pans_reproducible() {
+        let render = |seed| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    random_pan: float_param(1.0),
+                    random_pan_seed: int_param(seed),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events
+                .extend([60, 64, 67].map(|note| note_on(0, 0, note, None)));
+
+            host.render(&mut synth, 4800)
+        };
+
+        let [left, right] = render(7);
+        assert_ne!(left, right);
+        assert_eq!(render(7), [left.clone(), right]);
+        assert_ne!(render(8)[0], left);
+    }
+
+    #[test]
+    fn controller_changes_are_sample_accurate() {
+        let render = |events: &[PluginNoteEvent<PolyModSynth>]| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(PolyModSynthParams::default(), &host);
+            host.events.extend(events.iter().copied());
+            let [left, _] = host.render(&mut synth, 512);
+
+            left
+        };
+        let unchanged = render(&[note_on(0, 0, 69, None)]);
+
+        // The changes land in the middle of a block
+        for timing in [100, 150, 300] {
+            let changed = render(&[
+                note_on(0, 0, 69, None),
+                NoteEvent::MidiCC {
+                    timing,
+                    channel: 0,
+                    cc: EXPRESSION_CONTROLLER_MSB,
+                    value: 0.5,
+                },
+            ]);
+            let first_change = changed
+                .iter()
+                .zip(&unchanged)
+                .position(|(changed, unchanged)| changed != unchanged);
+            assert_eq!(first_change, Some(timing as usize));
+        }
+    }
+
+    #[test]
+    fn key_to_pan_spreads_notes_around_the_center() {
+        let peaks = |note| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    key_to_pan: float_param(0.5),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events.push_back(note_on(0, 0, note, None));
+            let [left, right] = host.render(&mut synth, 4800);
+
+            let peak = |samples: &[f32]| {
+                samples[480..]
+                    .iter()
+                    .fold(0.0f32, |peak, sample| peak.max(sample.abs()))
+            };
+            (peak(&left), peak(&right))
+        };
+        let center = KEY_TRACKING_CENTER;
+
+        // An octave away from the center is half way to the side, which is a quarter of the way
+        // around the constant power pan law's 90 degrees
+        let side_ratio = (std::f32::consts::FRAC_PI_4 + std::f32::consts::FRAC_PI_8).tan();
+        let (left, right) = peaks(center + 12);
+        assert!((right / left - side_ratio).abs() < 1e-2);
+        let (left, right) = peaks(center - 12);
+        assert!((left / right - side_ratio).abs() < 1e-2);
+        let (left, right) = peaks(center);
+        assert!((left - right).abs() < 1e-6);
+    }
+
+    #[test]
+    fn voice_drive_adds_harmonics_gradually() {
+        // The third harmonic relative to the fundamental, since tanh only adds odd harmonics
+        let distortion = |voice_drive| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    voice_drive: float_param(voice_drive),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events.push_back(note_on(0, 0, 69, None));
+            let [left, _] = host.render(&mut synth, 48000);
+
+            let samples = &left[4800..];
+            tone_amplitude(samples, 1320.0, host.sample_rate)
+                / tone_amplitude(samples, 440.0, host.sample_rate)
+        };
+
+        let drives = [0.0, 0.01, 1.0, 3.0, 6.0, 12.0, 24.0];
+        let distortion: Vec<f32> = drives.into_iter().map(distortion).collect();
+        assert!(distortion[0] < 1e-4);
+        assert!(distortion[1] < 1e-3, "{distortion:?}");
+        assert!(
+            distortion.windows(2).all(|pair| pair[1] > pair[0]),
+            "{distortion:?}"
+        );
+    }
+
+    #[test]
+    fn velocity_range_round_trips_through_dynamic_markings() {
+        let (v2s, s2v) = (velocity_range_to_string, string_to_velocity_range);
+        assert_eq!(v2s(30.0), "30.0 dB (pp-ff)");
+        assert_eq!(v2s(42.0), "42.0 dB (ppp-fff)");
+        assert_eq!(v2s(18.0), "18.0 dB (p-f)");
+        assert_eq!(v2s(2.0), "2.0 dB");
+        assert_eq!(s2v("pp-ff"), Some(30.0));
+        assert_eq!(s2v("ff..pp"), Some(30.0));
+        assert_eq!(s2v("mp\u{2013}mf"), Some(DYNAMICS_STEP_DB));
+        assert_eq!(s2v("12 dB"), Some(12.0));
+        assert_eq!(s2v("sfz-ff"), None);
+        for velocity_range in [0.0, 7.5, 30.0, 42.0, 100.0] {
+            assert_eq!(s2v(&v2s(velocity_range)), Some(velocity_range));
+        }
+
+        // With the full ppp-fff range, every marking is one step quieter than the next
+        let velocity_range = s2v("ppp-fff").unwrap();
+        for (step, &velocity) in DYNAMICS_VELOCITIES.iter().enumerate() {
+            let position = velocity_position(
+                velocity as f32 / 127.0,
+                VelocityInterpretation::Dynamics,
+                velocity_range,
+            );
+            let gain = velocity_gain(position, -velocity_range, 0.0, 0.0);
+            let expected_db = (step as f32 - 7.0) * DYNAMICS_STEP_DB;
+            assert!(
+                (util::gain_to_db(gain) - expected_db).abs() < 1e-3,
+                "{} at {}",
+                DYNAMICS_MARKINGS[step],
+                util::gain_to_db(gain)
+            );
+        }
+    }
+
+    #[test]
+    fn c5_base_note_plays_an_octave_up() {
+        let base_note = formatters::s2v_i32_note_formatter()("C5").unwrap();
+        assert_eq!(formatters::v2s_i32_note_formatter()(base_note), "C5");
+
+        let frequency = |base_note| {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    base_note: int_param(base_note),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+            host.events.push_back(note_on(0, 0, 69, None));
+            let [left, _] = host.render(&mut synth, 48000);
+
+            measure_frequency(&left[4800..], host.sample_rate)
+        };
+
+        assert!((frequency(UNTRANSPOSED_BASE_NOTE) - 440.0).abs() < 0.5);
+        assert!((frequency(base_note) - 880.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn stolen_voice_fades_last_for_the_declick_time() {
+        for (declick_time, fade_samples) in [(2.0, 96), (10.0, 480)] {
+            let mut host = TestHost::default();
+            let mut synth = test_synth(
+                PolyModSynthParams {
+                    gain: float_param(1.0),
+                    declick_time: float_param(declick_time),
+                    ..PolyModSynthParams::default()
+                },
+                &host,
+            );
+
+            // The new note is muted, so only the old note fading out is left after the steal
+            host.events.extend([
+                note_on(0, 0, 69, Some(1)),
+                note_on(1000, 0, 69, Some(2)),
+                NoteEvent::PolyVolume {
+                    timing: 1000,
+                    voice_id: Some(2),
+                    channel: 0,
+                    note: 69,
+                    gain: 0.0,
+                },
+            ]);
+            let [left, _] = host.render(&mut synth, 4800);
+
+            let peak = |samples: &[f32]| samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+            let fade_end = 1000 + fade_samples;
+            assert!(peak(&left[1000 + fade_samples / 2..fade_end]) <= 0.5 + 1e-3);
+            assert!(peak(&left[fade_end - fade_samples / 8..fade_end]) > 0.0);
+            assert_eq!(peak(&left[fade_end + 1..]), 0.0);
+        }
+    }
+}
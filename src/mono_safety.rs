@@ -0,0 +1,130 @@
+use std::ops::Range;
+
+/// The length of the window the stereo correlation is measured over.
+const CORRELATION_WINDOW_MS: f32 = 50.0;
+
+/// How quickly the width is reduced once the correlation drops below the threshold.
+const ATTACK_MS: f32 = 20.0;
+
+/// How quickly the width recovers once the correlation is above the threshold again.
+const RELEASE_MS: f32 = 500.0;
+
+/// Automatically narrows the stereo image when the output's correlation drops below a threshold,
+/// so the output doesn't cancel out when it's summed to mono. The correlation is measured over a
+/// sliding window, and the side signal is attenuated further the more negative the correlation
+/// gets. The mid signal is never touched.
+#[derive(Debug, Clone)]
+pub struct MonoSafety {
+    /// Exponentially weighted averages of `L * R`, `L * L`, and `R * R`.
+    products: [f32; 3],
+    /// The gain currently applied to the side signal.
+    side_gain: f32,
+}
+
+impl Default for MonoSafety {
+    fn default() -> Self {
+        Self {
+            products: [0.0; 3],
+            side_gain: 1.0,
+        }
+    }
+}
+
+impl MonoSafety {
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Measure the correlation of `output[..][range]` and reduce its width when the correlation is
+    /// below `threshold`.
+    pub fn process(
+        &mut self,
+        output: &mut [&mut [f32]],
+        range: Range<usize>,
+        threshold: f32,
+        sample_rate: f32,
+    ) {
+        let (left, right) = output.split_at_mut(1);
+        let (left, right) = (&mut left[0][range.clone()], &mut right[0][range]);
+
+        let window_coefficient = time_coefficient(CORRELATION_WINDOW_MS, sample_rate);
+        let [lr, ll, rr] = &mut self.products;
+        for (&l, &r) in left.iter().zip(right.iter()) {
+            *lr = l * r + (*lr - l * r) * window_coefficient;
+            *ll = l * l + (*ll - l * l) * window_coefficient;
+            *rr = r * r + (*rr - r * r) * window_coefficient;
+        }
+
+        let power = (*ll * *rr).sqrt();
+        let correlation = if power > 0.0 { *lr / power } else { 1.0 };
+        let target_gain = if correlation < threshold {
+            ((correlation + 1.0) / (threshold + 1.0)).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+
+        if target_gain == 1.0 && self.side_gain == 1.0 {
+            return;
+        }
+
+        let gain_coefficient = if target_gain < self.side_gain {
+            time_coefficient(ATTACK_MS, sample_rate)
+        } else {
+            time_coefficient(RELEASE_MS, sample_rate)
+        };
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            self.side_gain = target_gain + (self.side_gain - target_gain) * gain_coefficient;
+
+            let mid = (*l + *r) * 0.5;
+            let side = (*l - *r) * 0.5 * self.side_gain;
+            *l = mid + side;
+            *r = mid - side;
+        }
+
+        // The gain approaches the target exponentially, so it needs to snap back to unity at some
+        // point for the early return above to kick in again
+        if self.side_gain > 0.9999 && target_gain == 1.0 {
+            self.side_gain = 1.0;
+        }
+    }
+}
+
+/// The coefficient for a one-pole smoother with a time constant of `time_ms`.
+fn time_coefficient(time_ms: f32, sample_rate: f32) -> f32 {
+    (-1.0 / (time_ms / 1000.0 * sample_rate)).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anti_correlated_input_loses_its_width() {
+        let process = |left: &mut [f32], right: &mut [f32]| {
+            let mut mono_safety = MonoSafety::default();
+            for block_start in (0..left.len()).step_by(64) {
+                mono_safety.process(
+                    &mut [left, right],
+                    block_start..block_start + 64,
+                    0.0,
+                    48000.0,
+                );
+            }
+        };
+        let sine: Vec<f32> = (0..48000).map(|idx| (idx as f32 * 0.05).sin()).collect();
+
+        // The side signal is pulled down to nothing, while the mid signal is left alone
+        let mut left = sine.clone();
+        let mut right: Vec<f32> = sine.iter().map(|sample| sample * -0.8).collect();
+        process(&mut left, &mut right);
+        for ((left, right), sine) in left.iter().zip(&right).zip(&sine).skip(24000) {
+            assert!(((left + right) / 2.0 - sine * 0.1).abs() < 1e-4);
+            assert!((left - right).abs() < 1e-3);
+        }
+
+        // Correlated input passes through untouched
+        let (mut left, mut right) = (sine.clone(), sine.clone());
+        process(&mut left, &mut right);
+        assert_eq!([left, right], [sine.clone(), sine]);
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    f32::consts::{PI, TAU},
+    ops::Range,
+};
+
+use crate::PolyModSynthParams;
+
+/// The most allpass stages the phaser can use.
+pub const MAX_STAGES: usize = 12;
+
+/// The frequency the allpass stages are swept around.
+const CENTER_FREQUENCY: f32 = 800.0;
+
+/// How far the sweep goes above and below the center frequency at full depth.
+const SWEEP_OCTAVES: f32 = 3.0;
+
+/// A stereo phaser. The input runs through a chain of first order allpass filters whose corner
+/// frequency is swept by an LFO, and blending that with the dry signal creates notches that move
+/// through the spectrum. The notches are deepest at a 50% mix. The right channel's LFO runs a
+/// quarter cycle ahead of the left channel's, so the sweep moves across the stereo field.
+#[derive(Debug, Clone, Default)]
+pub struct Phaser {
+    lfo_phase: f32,
+    /// The state of every allpass stage, for each channel.
+    stages: [[f32; MAX_STAGES]; 2],
+    /// The last output of the allpass chain, for each channel, which gets fed back into its input.
+    feedback: [f32; 2],
+}
+
+impl Phaser {
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Run the phaser on `output[..][range]`.
+    pub fn process(
+        &mut self,
+        output: &mut [&mut [f32]],
+        range: Range<usize>,
+        params: &PolyModSynthParams,
+        sample_rate: f32,
+    ) {
+        let mix = params.phaser_mix.value();
+
+        // The state is cleared while the phaser is off so it doesn't ring out old signal when
+        // it's turned back on
+        if mix == 0.0 {
+            self.reset();
+            return;
+        }
+
+        let num_stages = (params.phaser_stages.value() as usize).clamp(1, MAX_STAGES);
+        let lfo_delta = params.phaser_rate.value() / sample_rate;
+        let depth = params.phaser_depth.value();
+        let feedback = params.phaser_feedback.value();
+
+        let (left, right) = output.split_at_mut(1);
+        let (left, right) = (&mut left[0][range.clone()], &mut right[0][range]);
+        for (left, right) in left.iter_mut().zip(right.iter_mut()) {
+            for (channel, sample) in [left, right].into_iter().enumerate() {
+                let lfo = (TAU * (self.lfo_phase + channel as f32 * 0.25)).sin();
+                let frequency = (CENTER_FREQUENCY * 2.0f32.powf(lfo * depth * SWEEP_OCTAVES))
+                    .min(sample_rate * 0.49);
+                let tan = (PI * frequency / sample_rate).tan();
+                let coefficient = (tan - 1.0) / (tan + 1.0);
+
+                let dry = *sample;
+                let mut wet = dry + self.feedback[channel] * feedback;
+                for state in &mut self.stages[channel][..num_stages] {
+                    let allpassed = coefficient * wet + *state;
+                    *state = wet - coefficient * allpassed;
+                    wet = allpassed;
+                }
+                self.feedback[channel] = wet;
+
+                *sample = dry + (wet - dry) * mix;
+            }
+
+            self.lfo_phase = (self.lfo_phase + lfo_delta).fract();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_host::{float_param, int_param};
+
+    #[test]
+    fn notches_sweep_through_the_spectrum() {
+        // With two stages and a 50% mix there's a single notch at the allpass corner frequency,
+        // which sweeps from 100 Hz to 6.4 kHz and back over one LFO cycle
+        let params = PolyModSynthParams {
+            phaser_stages: int_param(2),
+            phaser_rate: float_param(0.5),
+            phaser_depth: float_param(1.0),
+            phaser_feedback: float_param(0.0),
+            phaser_mix: float_param(0.5),
+            ..PolyModSynthParams::default()
+        };
+        let sample_rate = 48000.0;
+        let window = 480;
+
+        // The level of a sine at `frequency` after the phaser, in 10 ms windows
+        let levels = |frequency: f32| -> Vec<f32> {
+            let mut phaser = Phaser::default();
+            let mut left: Vec<f32> = (0..96000)
+                .map(|idx| (TAU * frequency * idx as f32 / sample_rate).sin())
+                .collect();
+            let mut right = left.clone();
+            for block_start in (0..left.len()).step_by(64) {
+                phaser.process(
+                    &mut [&mut left, &mut right],
+                    block_start..block_start + 64,
+                    &params,
+                    sample_rate,
+                );
+            }
+
+            left.chunks(window)
+                .map(|chunk| chunk.iter().map(|sample| sample.abs()).fold(0.0, f32::max))
+                .collect()
+        };
+
+        // Each tone gets notched out at some point in the sweep and passes through at others
+        let (low, high) = (levels(400.0), levels(1600.0));
+        for levels in [&low, &high] {
+            assert!(levels.iter().any(|&level| level < 0.2), "{levels:?}");
+            assert!(levels.iter().any(|&level| level > 0.95), "{levels:?}");
+        }
+
+        // The LFO starts by sweeping up, so the notch passes 1.6 kHz about 108 ms in and comes
+        // back down through 400 Hz half a cycle later
+        let deepest = |levels: &[f32]| {
+            (0..levels.len())
+                .min_by(|&a, &b| levels[a].total_cmp(&levels[b]))
+                .unwrap()
+        };
+        assert!((9..=12).contains(&deepest(&high[..50])), "{high:?}");
+        assert!((109..=112).contains(&deepest(&low[..150])), "{low:?}");
+    }
+}
@@ -0,0 +1,190 @@
+use std::ops::Range;
+
+// These tunings come from Jezar's public domain Freeverb, and they are in samples at 44.1 kHz
+const COMB_TUNINGS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNINGS: [usize; 4] = [556, 441, 341, 225];
+const TUNING_SAMPLE_RATE: f32 = 44100.0;
+
+/// The right channel's delay lines are this many samples longer than the left channel's, which
+/// decorrelates the two channels.
+const STEREO_SPREAD: usize = 23;
+
+const FIXED_GAIN: f32 = 0.015;
+const SCALE_WET: f32 = 3.0;
+const SCALE_DAMPING: f32 = 0.4;
+const SCALE_ROOM: f32 = 0.28;
+const OFFSET_ROOM: f32 = 0.7;
+const ALLPASS_FEEDBACK: f32 = 0.5;
+
+/// A Freeverb-style stereo reverb: eight parallel lowpass-feedback comb filters followed by four
+/// series allpass filters per channel.
+#[derive(Debug, Clone, Default)]
+pub struct Reverb {
+    combs: [Vec<Comb>; 2],
+    allpasses: [Vec<Allpass>; 2],
+    sample_rate: f32,
+}
+
+#[derive(Debug, Clone)]
+struct Comb {
+    buffer: Vec<f32>,
+    index: usize,
+    filter_store: f32,
+}
+
+#[derive(Debug, Clone)]
+struct Allpass {
+    buffer: Vec<f32>,
+    index: usize,
+}
+
+impl Reverb {
+    /// Scale the Freeverb delay tunings to `sample_rate` and allocate the delay lines.
+    pub fn initialize(&mut self, sample_rate: f32) {
+        if !crate::sample_rate_changed(&mut self.sample_rate, sample_rate) {
+            return;
+        }
+
+        let scale = sample_rate / TUNING_SAMPLE_RATE;
+        let delay = |tuning: usize| ((tuning as f32 * scale).round() as usize).max(1);
+        for (channel, spread) in [0, STEREO_SPREAD].into_iter().enumerate() {
+            self.combs[channel] = COMB_TUNINGS
+                .iter()
+                .map(|&tuning| Comb::new(delay(tuning + spread)))
+                .collect();
+            self.allpasses[channel] = ALLPASS_TUNINGS
+                .iter()
+                .map(|&tuning| Allpass::new(delay(tuning + spread)))
+                .collect();
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for comb in self.combs.iter_mut().flatten() {
+            comb.reset();
+        }
+        for allpass in self.allpasses.iter_mut().flatten() {
+            allpass.reset();
+        }
+    }
+
+    /// Replace `output[..][range]` with its fully wet reverb. `size` and `damping` are both in
+    /// `[0, 1]`.
+    pub fn process(
+        &mut self,
+        output: &mut [&mut [f32]],
+        range: Range<usize>,
+        size: f32,
+        damping: f32,
+    ) {
+        let feedback = size * SCALE_ROOM + OFFSET_ROOM;
+        let damping = damping * SCALE_DAMPING;
+
+        for sample_idx in range {
+            let input = (output[0][sample_idx] + output[1][sample_idx]) * FIXED_GAIN;
+
+            for (channel, (combs, allpasses)) in self
+                .combs
+                .iter_mut()
+                .zip(self.allpasses.iter_mut())
+                .enumerate()
+            {
+                let mut wet_sample: f32 = combs
+                    .iter_mut()
+                    .map(|comb| comb.process(input, feedback, damping))
+                    .sum();
+                for allpass in allpasses.iter_mut() {
+                    wet_sample = allpass.process(wet_sample);
+                }
+
+                output[channel][sample_idx] = wet_sample * SCALE_WET;
+            }
+        }
+    }
+}
+
+impl Comb {
+    fn new(length: usize) -> Self {
+        Self {
+            buffer: vec![0.0; length],
+            index: 0,
+            filter_store: 0.0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.index = 0;
+        self.filter_store = 0.0;
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer[self.index];
+        self.filter_store = output * (1.0 - damping) + self.filter_store * damping;
+        self.buffer[self.index] = input + self.filter_store * feedback;
+
+        self.index += 1;
+        if self.index >= self.buffer.len() {
+            self.index = 0;
+        }
+
+        output
+    }
+}
+
+impl Allpass {
+    fn new(length: usize) -> Self {
+        Self {
+            buffer: vec![0.0; length],
+            index: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.index = 0;
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let buffered = self.buffer[self.index];
+        self.buffer[self.index] = input + buffered * ALLPASS_FEEDBACK;
+
+        self.index += 1;
+        if self.index >= self.buffer.len() {
+            self.index = 0;
+        }
+
+        buffered - input
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn impulse_rings_out_in_a_decaying_tail() {
+        let mut reverb = Reverb::default();
+        reverb.initialize(48000.0);
+
+        let mut left = vec![0.0; 96000];
+        let mut right = vec![0.0; 96000];
+        left[0] = 1.0;
+        right[0] = 1.0;
+        reverb.process(&mut [&mut left, &mut right], 0..96000, 0.5, 0.5);
+
+        // The energy of every tenth of a second, which should keep going down once the tail has
+        // built up
+        let energy: Vec<f32> = left
+            .chunks(4800)
+            .map(|window| window.iter().map(|sample| sample * sample).sum())
+            .collect();
+        assert!(energy[1] > 0.0);
+        assert!(
+            energy[1..].windows(2).all(|pair| pair[1] < pair[0]),
+            "{energy:?}"
+        );
+        assert!(energy[19] < energy[1] * 1e-3);
+        assert_ne!(left, right);
+    }
+}
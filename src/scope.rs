@@ -0,0 +1,136 @@
+use atomic_float::AtomicF32;
+use std::sync::{
+    Arc,
+    atomic::{AtomicU32, Ordering},
+};
+
+/// The number of samples per channel in a captured oscilloscope frame.
+pub const FRAME_LEN: usize = 1024;
+
+/// The most recently captured oscilloscope frame, shared between the audio thread and the editor.
+/// Every frame starts at a rising crossing of the trigger level, so consecutive frames of a
+/// periodic signal line up on the display.
+pub struct ScopeFrame {
+    samples: [[AtomicF32; FRAME_LEN]; 2],
+    /// Incremented every time a new frame has been published, so the editor can tell whether it
+    /// needs to redraw.
+    generation: AtomicU32,
+}
+
+/// Captures triggered frames of the output on the audio thread and publishes them to a
+/// [`ScopeFrame`]. This never allocates or locks, and the samples are only copied to the shared
+/// frame once a capture is complete.
+pub struct Scope {
+    frame: Arc<ScopeFrame>,
+    capture: [[f32; FRAME_LEN]; 2],
+    /// The number of samples captured so far, or `None` while waiting for the trigger.
+    capture_len: Option<usize>,
+    /// The last left channel sample, used to detect crossings across buffer boundaries.
+    previous_sample: f32,
+}
+
+impl ScopeFrame {
+    /// Copy the latest frame into `frame`, returning the frame's generation. The copy may mix
+    /// samples of two frames if a new frame gets published while reading, which is fine for
+    /// display purposes.
+    pub fn read(&self, frame: &mut [[f32; FRAME_LEN]; 2]) -> u32 {
+        let generation = self.generation.load(Ordering::Acquire);
+        for (frame, samples) in frame.iter_mut().zip(&self.samples) {
+            for (sample, shared) in frame.iter_mut().zip(samples) {
+                *sample = shared.load(Ordering::Relaxed);
+            }
+        }
+
+        generation
+    }
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Self {
+            frame: Arc::new(ScopeFrame {
+                samples: std::array::from_fn(|_| std::array::from_fn(|_| AtomicF32::new(0.0))),
+                generation: AtomicU32::new(0),
+            }),
+            capture: [[0.0; FRAME_LEN]; 2],
+            capture_len: None,
+            previous_sample: 0.0,
+        }
+    }
+}
+
+impl Scope {
+    /// The shared frame, for the editor to read from.
+    pub fn frame(&self) -> Arc<ScopeFrame> {
+        self.frame.clone()
+    }
+
+    pub fn reset(&mut self) {
+        self.capture_len = None;
+        self.previous_sample = 0.0;
+    }
+
+    /// Capture the stereo `output`. A new capture starts whenever the left channel rises through
+    /// `trigger_level` while no capture is in progress.
+    pub fn process(&mut self, output: &[&mut [f32]], trigger_level: f32) {
+        for (&left, &right) in output[0].iter().zip(output[1].iter()) {
+            let triggered = self.previous_sample < trigger_level && left >= trigger_level;
+            self.previous_sample = left;
+
+            let capture_len = match self.capture_len {
+                Some(capture_len) => capture_len,
+                None if triggered => 0,
+                None => continue,
+            };
+
+            self.capture[0][capture_len] = left;
+            self.capture[1][capture_len] = right;
+            if capture_len + 1 < FRAME_LEN {
+                self.capture_len = Some(capture_len + 1);
+            } else {
+                self.publish();
+                self.capture_len = None;
+            }
+        }
+    }
+
+    fn publish(&self) {
+        for (capture, samples) in self.capture.iter().zip(&self.frame.samples) {
+            for (&sample, shared) in capture.iter().zip(samples) {
+                shared.store(sample, Ordering::Relaxed);
+            }
+        }
+        self.frame.generation.fetch_add(1, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_start_at_the_trigger() {
+        let mut scope = Scope::default();
+        let frame = scope.frame();
+
+        let mut left: Vec<f32> = (0..FRAME_LEN * 2)
+            .map(|i| (i as f32 * 0.05 - 1.0).sin())
+            .collect();
+        let mut right = left.clone();
+        scope.process(
+            &[&mut left[..FRAME_LEN / 2], &mut right[..FRAME_LEN / 2]],
+            0.0,
+        );
+        assert_eq!(frame.read(&mut [[0.0; FRAME_LEN]; 2]), 0);
+        scope.process(
+            &[&mut left[FRAME_LEN / 2..], &mut right[FRAME_LEN / 2..]],
+            0.0,
+        );
+
+        let mut captured = [[0.0; FRAME_LEN]; 2];
+        assert_eq!(frame.read(&mut captured), 1);
+        let trigger = left.iter().position(|&sample| sample >= 0.0).unwrap();
+        assert_eq!(captured[0][..], left[trigger..trigger + FRAME_LEN]);
+        assert_eq!(captured[1], captured[0]);
+    }
+}
@@ -0,0 +1,202 @@
+use nih_plug::prelude::*;
+use std::{collections::VecDeque, sync::Arc};
+
+use crate::{PolyModSynth, PolyModSynthParams, ProcessHost};
+
+/// A scripted stand-in for the host's process context, for rendering deterministically.
+pub struct TestHost {
+    pub sample_rate: f32,
+    pub tempo: Option<f64>,
+    pub pos_beats: Option<f64>,
+    pub playing: bool,
+    /// The events for the next rendered buffer, in order. Their timings are relative to the
+    /// start of that buffer.
+    pub events: VecDeque<PluginNoteEvent<PolyModSynth>>,
+    /// Every event the synth sent back to the host.
+    pub sent_events: Vec<PluginNoteEvent<PolyModSynth>>,
+}
+
+impl Default for TestHost {
+    fn default() -> Self {
+        Self {
+            sample_rate: 48000.0,
+            tempo: None,
+            pos_beats: None,
+            playing: false,
+            events: VecDeque::new(),
+            sent_events: Vec::new(),
+        }
+    }
+}
+
+impl ProcessHost for TestHost {
+    fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    fn playing(&self) -> bool {
+        self.playing
+    }
+
+    #[cfg(debug_assertions)]
+    fn tempo(&self) -> Option<f64> {
+        self.tempo
+    }
+
+    #[cfg(debug_assertions)]
+    fn pos_beats(&self) -> Option<f64> {
+        self.pos_beats
+    }
+
+    fn next_event(&mut self) -> Option<PluginNoteEvent<PolyModSynth>> {
+        self.events.pop_front()
+    }
+
+    fn send_event(&mut self, event: PluginNoteEvent<PolyModSynth>) {
+        self.sent_events.push(event);
+    }
+}
+
+impl InitContext<PolyModSynth> for TestHost {
+    fn plugin_api(&self) -> PluginApi {
+        PluginApi::Clap
+    }
+
+    fn execute(&self, _task: ()) {}
+
+    fn set_latency_samples(&self, _samples: u32) {}
+
+    fn set_current_voice_capacity(&self, _capacity: u32) {}
+}
+
+impl TestHost {
+    /// Render a stereo buffer of `num_samples` samples with `synth`. The song position moves
+    /// forward by the length of the buffer while the transport is playing.
+    pub fn render(&mut self, synth: &mut PolyModSynth, num_samples: usize) -> [Vec<f32>; 2] {
+        self.render_with_aux(synth, num_samples, 0).swap_remove(0)
+    }
+
+    /// Like [`render()`][Self::render()], but with `num_aux_outputs` stereo aux outputs. Returns
+    /// the main output followed by the aux outputs.
+    pub fn render_with_aux(
+        &mut self,
+        synth: &mut PolyModSynth,
+        num_samples: usize,
+        num_aux_outputs: usize,
+    ) -> Vec<[Vec<f32>; 2]> {
+        let mut outputs =
+            vec![[vec![0.0; num_samples], vec![0.0; num_samples]]; num_aux_outputs + 1];
+        let ([left, right], aux) = outputs.split_first_mut().unwrap();
+        let mut aux_channels: Vec<[&mut [f32]; 2]> = aux
+            .iter_mut()
+            .map(|[left, right]| [left.as_mut_slice(), right.as_mut_slice()])
+            .collect();
+        let mut aux_outputs: Vec<&mut [&mut [f32]]> = aux_channels
+            .iter_mut()
+            .map(|channels| channels.as_mut_slice())
+            .collect();
+        synth.process_buffer(&mut [left, right], &mut aux_outputs, self);
+
+        if self.playing
+            && let (Some(pos_beats), Some(tempo)) = (&mut self.pos_beats, self.tempo)
+        {
+            *pos_beats += num_samples as f64 * tempo / 60.0 / self.sample_rate as f64;
+        }
+
+        outputs
+    }
+}
+
+/// A synth with `params` that's ready to render at `host`'s sample rate.
+pub fn test_synth(params: PolyModSynthParams, host: &TestHost) -> PolyModSynth {
+    let mut synth = PolyModSynth {
+        params: Arc::new(params),
+        ..PolyModSynth::default()
+    };
+    synth.allocate(host.sample_rate);
+    synth.reset();
+
+    synth
+}
+
+pub fn note_on(
+    timing: u32,
+    channel: u8,
+    note: u8,
+    voice_id: Option<i32>,
+) -> PluginNoteEvent<PolyModSynth> {
+    NoteEvent::NoteOn {
+        timing,
+        voice_id,
+        channel,
+        note,
+        velocity: 1.0,
+    }
+}
+
+pub fn note_off(timing: u32, channel: u8, note: u8) -> PluginNoteEvent<PolyModSynth> {
+    NoteEvent::NoteOff {
+        timing,
+        voice_id: None,
+        channel,
+        note,
+        velocity: 0.0,
+    }
+}
+
+/// A parameter that's fixed at `value`, for building test parameter sets.
+pub fn float_param(value: f32) -> FloatParam {
+    FloatParam::new(
+        "",
+        value,
+        FloatRange::Linear {
+            min: value - 1.0,
+            max: value + 1.0,
+        },
+    )
+}
+
+pub fn int_param(value: i32) -> IntParam {
+    IntParam::new(
+        "",
+        value,
+        IntRange::Linear {
+            min: value - 1,
+            max: value + 1,
+        },
+    )
+}
+
+/// The positions of every upward zero crossing in `samples`, linearly interpolated between the
+/// samples around them.
+pub fn rising_crossings(samples: &[f32]) -> Vec<f32> {
+    samples
+        .windows(2)
+        .enumerate()
+        .filter(|(_, pair)| pair[0] < 0.0 && pair[1] >= 0.0)
+        .map(|(idx, pair)| idx as f32 + pair[0] / (pair[0] - pair[1]))
+        .collect()
+}
+
+/// Estimate the frequency of a periodic signal from the time between its first and last rising
+/// zero crossing. This is accurate to well below a hertz for a second of a pure tone.
+pub fn measure_frequency(samples: &[f32], sample_rate: f32) -> f32 {
+    let crossings = rising_crossings(samples);
+    assert!(crossings.len() >= 2, "the signal doesn't cross zero");
+
+    let periods = (crossings.len() - 1) as f32;
+    periods / (crossings[crossings.len() - 1] - crossings[0]) * sample_rate
+}
+
+/// The amplitude of the `frequency` component in `samples`, from a single bin of a discrete Fourier
+/// transform. For an exact result `samples` should hold a whole number of cycles.
+pub fn tone_amplitude(samples: &[f32], frequency: f32, sample_rate: f32) -> f32 {
+    let (mut sin_sum, mut cos_sum) = (0.0f64, 0.0f64);
+    for (idx, &sample) in samples.iter().enumerate() {
+        let phase = std::f64::consts::TAU * frequency as f64 * idx as f64 / sample_rate as f64;
+        sin_sum += sample as f64 * phase.sin();
+        cos_sum += sample as f64 * phase.cos();
+    }
+
+    (2.0 * sin_sum.hypot(cos_sum) / samples.len() as f64) as f32
+}
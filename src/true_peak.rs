@@ -0,0 +1,209 @@
+use std::ops::Range;
+
+/// How many points we'll evaluate per sample period when estimating the inter-sample peaks.
+const OVERSAMPLING_FACTOR: usize = 4;
+
+/// Keeps the true (inter-sample) peak of the stereo output below a ceiling. Unlike a sample-peak
+/// limiter this also catches the overs that only show up after the DAC reconstructs the signal.
+/// The peaks are estimated by interpolating the output at a higher rate and fed into a peak
+/// envelope follower with separate attack and release times. The gain reduction derived from that
+/// envelope is applied per block so the signal shape itself is left untouched.
+#[derive(Debug, Clone)]
+pub struct TruePeakLimiter {
+    /// The last three samples of each channel, oldest first. These are needed to interpolate across
+    /// block boundaries.
+    history: [[f32; 3]; 2],
+    /// The envelope of the estimated true peak level.
+    envelope: f32,
+    /// The gain that was applied at the end of the last block.
+    gain: f32,
+}
+
+impl Default for TruePeakLimiter {
+    fn default() -> Self {
+        Self {
+            history: [[0.0; 3]; 2],
+            envelope: 0.0,
+            gain: 1.0,
+        }
+    }
+}
+
+impl TruePeakLimiter {
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Estimate the true peak of `output[..][range]` and reduce the block's gain just enough to
+    /// keep the peak envelope under `ceiling` (as linear gain). The envelope rises towards new peaks
+    /// over `attack_ms` and falls back over `release_ms`. With an attack time of zero, every peak
+    /// is caught by reducing the gain for the entire block.
+    pub fn process(
+        &mut self,
+        output: &mut [&mut [f32]],
+        range: Range<usize>,
+        ceiling: f32,
+        attack_ms: f32,
+        release_ms: f32,
+        sample_rate: f32,
+    ) {
+        let mut peak: f32 = 0.0;
+        for (channel, history) in output.iter().zip(self.history.iter_mut()) {
+            for &sample in &channel[range.clone()] {
+                let [a, b, c] = *history;
+                peak = peak.max(interpolated_peak(a, b, c, sample));
+                *history = [b, c, sample];
+            }
+        }
+
+        let block_len = range.len();
+        let time_ms = if peak > self.envelope {
+            attack_ms
+        } else {
+            release_ms
+        };
+        let coefficient = if time_ms > 0.0 {
+            (-(block_len as f32) / (time_ms / 1000.0 * sample_rate)).exp()
+        } else {
+            0.0
+        };
+        self.envelope = peak + (self.envelope - peak) * coefficient;
+        let new_gain = if self.envelope > ceiling {
+            ceiling / self.envelope
+        } else {
+            1.0
+        };
+
+        // Without an attack time the entire block needs to be attenuated when reducing gain, and
+        // otherwise we'll ramp between the old and the new gain
+        let start_gain = if attack_ms > 0.0 {
+            self.gain
+        } else {
+            self.gain.min(new_gain)
+        };
+        for channel in output.iter_mut() {
+            for (i, sample) in channel[range.clone()].iter_mut().enumerate() {
+                let t = (i + 1) as f32 / block_len as f32;
+                *sample *= start_gain + (new_gain - start_gain) * t;
+            }
+        }
+
+        self.gain = new_gain;
+    }
+}
+
+/// The largest absolute value of the segment between `b` and `c`, including the samples
+/// themselves, using a Catmull-Rom spline through `a`, `b`, `c`, and `d`.
+fn interpolated_peak(a: f32, b: f32, c: f32, d: f32) -> f32 {
+    (1..OVERSAMPLING_FACTOR)
+        .map(|i| catmull_rom(a, b, c, d, i as f32 / OVERSAMPLING_FACTOR as f32).abs())
+        .fold(c.abs().max(d.abs()), f32::max)
+}
+
+fn catmull_rom(a: f32, b: f32, c: f32, d: f32, t: f32) -> f32 {
+    0.5 * (2.0 * b
+        + (c - a) * t
+        + (2.0 * a - 5.0 * b + 4.0 * c - d) * t * t
+        + (3.0 * b - a - 3.0 * c + d) * t * t * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolated_peak_finds_inter_sample_overs() {
+        // A quarter sample rate sine at a 45 degree phase offset never has a sample at its peak.
+        // All samples are at full scale, but the reconstructed signal goes over between them.
+        let peak = interpolated_peak(-1.0, 1.0, 1.0, -1.0);
+        assert!((peak - 1.25).abs() < 1e-6);
+
+        // A signal that peaks on a sample doesn't have any overs
+        assert_eq!(interpolated_peak(0.0, 0.5, 1.0, 0.5), 1.0);
+        assert_eq!(interpolated_peak(0.0, 0.0, 0.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn limited_output_stays_below_the_ceiling() {
+        // A hot quarter sample rate sine that only goes over between the samples, starting in the
+        // middle of a block
+        let sample = |idx: usize| match idx {
+            0..100 => 0.0,
+            _ => [1.0, 1.0, -1.0, -1.0][idx % 4],
+        };
+        let mut left: Vec<f32> = (0..1024).map(sample).collect();
+        let mut right = left.clone();
+
+        let ceiling = 10.0f32.powf(-1.0 / 20.0);
+        let mut limiter = TruePeakLimiter::default();
+        for block_start in (0..left.len()).step_by(64) {
+            limiter.process(
+                &mut [&mut left, &mut right],
+                block_start..block_start + 64,
+                ceiling,
+                0.0,
+                100.0,
+                48000.0,
+            );
+        }
+
+        let peak = left
+            .windows(4)
+            .map(|window| interpolated_peak(window[0], window[1], window[2], window[3]))
+            .fold(0.0, f32::max);
+        assert!(peak <= ceiling + 1e-6, "true peak {peak}");
+        assert!(peak > ceiling - 1e-3);
+        assert_eq!(left, right);
+    }
+
+    #[test]
+    fn gain_recovers_over_the_release_time() {
+        // A full scale burst followed by quiet material, which doesn't need any gain reduction
+        let input: Vec<f32> = (0..48000)
+            .map(|idx| if idx < 640 { 1.0 } else { 0.1 })
+            .collect();
+        let mut left = input.clone();
+        let mut right = input.clone();
+
+        let (ceiling, release_ms, sample_rate) = (0.5, 50.0, 48000.0);
+        let mut limiter = TruePeakLimiter::default();
+        for block_start in (0..input.len()).step_by(64) {
+            limiter.process(
+                &mut [&mut left, &mut right],
+                block_start..block_start + 64,
+                ceiling,
+                0.0,
+                release_ms,
+                sample_rate,
+            );
+        }
+
+        // The envelope can fall by at most this factor per block, so that's also the limit for how
+        // much the gain can rise per block
+        let max_block_ratio = (64.0 / (release_ms / 1000.0 * sample_rate)).exp();
+        let gain: Vec<f32> = left
+            .iter()
+            .zip(&input)
+            .map(|(limited, sample)| limited / sample)
+            .collect();
+        let recovery = &gain[640..];
+        assert!(recovery[0] < 0.6);
+        assert!(recovery.windows(2).all(|pair| pair[1] >= pair[0]));
+        for blocks in recovery.chunks(64).collect::<Vec<_>>().windows(2) {
+            let ratio = blocks[1][63] / blocks[0][63];
+            assert!(ratio <= max_block_ratio + 1e-5, "gain rose by {ratio}");
+        }
+
+        // The envelope decays from the burst's peak towards the quiet material's level, and the
+        // gain is back at unity once that crosses the ceiling. The envelope only moves once per
+        // block, and the block right after the burst still sees it in the interpolation history.
+        let release_samples = release_ms / 1000.0 * sample_rate;
+        let expected = (release_samples * ((1.0 - 0.1) / (ceiling - 0.1)).ln()) as usize;
+        let recovered = recovery.iter().position(|&gain| gain == 1.0).unwrap();
+        assert!(
+            (expected..expected + 4 * 64).contains(&recovered),
+            "recovered after {recovered} samples, expected {expected}"
+        );
+        assert!(recovery[recovered..].iter().all(|&gain| gain == 1.0));
+    }
+}